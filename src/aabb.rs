@@ -0,0 +1,118 @@
+use crate::{interval::Interval, ray::Ray, vector::Point3};
+
+// Axis aligned bounding box, stored as one interval per axis
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub x: Interval,
+    pub y: Interval,
+    pub z: Interval,
+}
+
+impl Default for Aabb {
+    fn default() -> Self {
+        Aabb::EMPTY
+    }
+}
+
+impl Aabb {
+    pub const EMPTY: Aabb = Aabb {
+        x: Interval::EMPTY,
+        y: Interval::EMPTY,
+        z: Interval::EMPTY,
+    };
+    pub const UNIVERSE: Aabb = Aabb {
+        x: Interval::UNIVERSE,
+        y: Interval::UNIVERSE,
+        z: Interval::UNIVERSE,
+    };
+
+    pub fn new(x: Interval, y: Interval, z: Interval) -> Aabb {
+        let mut bbox = Aabb { x, y, z };
+        bbox.pad_to_minimums();
+        bbox
+    }
+
+    // Treat the two points as extrema of the box, in any order
+    pub fn from_points(a: &Point3, b: &Point3) -> Aabb {
+        let x = Interval::new(a.x().min(b.x()), a.x().max(b.x()));
+        let y = Interval::new(a.y().min(b.y()), a.y().max(b.y()));
+        let z = Interval::new(a.z().min(b.z()), a.z().max(b.z()));
+        Aabb::new(x, y, z)
+    }
+
+    pub fn enclosing(a: &Aabb, b: &Aabb) -> Aabb {
+        Aabb {
+            x: Interval::enclosing(&a.x, &b.x),
+            y: Interval::enclosing(&a.y, &b.y),
+            z: Interval::enclosing(&a.z, &b.z),
+        }
+    }
+
+    pub fn axis_interval(&self, n: usize) -> &Interval {
+        match n {
+            1 => &self.y,
+            2 => &self.z,
+            _ => &self.x,
+        }
+    }
+
+    pub fn centroid(&self, n: usize) -> f64 {
+        let interval = self.axis_interval(n);
+        0.5 * (interval.min + interval.max)
+    }
+
+    pub fn longest_axis(&self) -> usize {
+        if self.x.size() > self.y.size() {
+            if self.x.size() > self.z.size() {
+                0
+            } else {
+                2
+            }
+        } else if self.y.size() > self.z.size() {
+            1
+        } else {
+            2
+        }
+    }
+
+    // Slab test, narrowing ray_t against each axis in turn
+    pub fn hit(&self, ray: &Ray, mut ray_t: Interval) -> bool {
+        let origin = ray.origin();
+        let direction = ray.direction();
+
+        for axis in 0..3 {
+            let ax = self.axis_interval(axis);
+            let adinv = 1.0 / direction[axis];
+
+            let t0 = (ax.min - origin[axis]) * adinv;
+            let t1 = (ax.max - origin[axis]) * adinv;
+
+            if t0 < t1 {
+                ray_t.min = ray_t.min.max(t0);
+                ray_t.max = ray_t.max.min(t1);
+            } else {
+                ray_t.min = ray_t.min.max(t1);
+                ray_t.max = ray_t.max.min(t0);
+            }
+
+            if ray_t.max <= ray_t.min {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn pad_to_minimums(&mut self) {
+        // Flat boxes (e.g. a triangle lying in an axis plane) would never be hit by the slab test
+        let delta = 0.0001;
+        if self.x.size() < delta {
+            self.x = self.x.expand(delta);
+        }
+        if self.y.size() < delta {
+            self.y = self.y.expand(delta);
+        }
+        if self.z.size() < delta {
+            self.z = self.z.expand(delta);
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use crate::{color::Color, ray::Ray};
+
+// What a ray sees when it escapes the scene
+#[derive(Debug, Clone, Copy)]
+pub enum Background {
+    Solid(Color),
+    // Blend from `bottom` straight down to `top` straight up
+    Gradient { bottom: Color, top: Color },
+}
+
+impl Default for Background {
+    // The white to blue sky from the book
+    fn default() -> Self {
+        Background::Gradient {
+            bottom: Color::new(1.0, 1.0, 1.0),
+            top: Color::new(0.5, 0.7, 1.0),
+        }
+    }
+}
+
+impl Background {
+    pub const BLACK: Background = Background::Solid(Color::new(0.0, 0.0, 0.0));
+
+    pub fn color(&self, ray: &Ray) -> Color {
+        match self {
+            Background::Solid(color) => *color,
+            Background::Gradient { bottom, top } => {
+                let unit_direction = ray.direction().normalize();
+                let t = 0.5 * (unit_direction.y() + 1.0);
+                *bottom * (1.0 - t) + *top * t
+            }
+        }
+    }
+}
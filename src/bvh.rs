@@ -0,0 +1,201 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::Aabb,
+    hittable::{HitRecord, Hittable, HittableList},
+    interval::Interval,
+    ray::Ray,
+};
+
+// Bounding volume hierarchy over a set of hittables. Each node splits its objects at the median
+// centroid along the longest axis of its bounding box, so a ray only descends into the children
+// whose boxes it actually crosses.
+#[derive(Clone)]
+pub struct BvhNode {
+    left: Arc<dyn Hittable>,
+    right: Arc<dyn Hittable>,
+    bbox: Aabb,
+}
+
+impl BvhNode {
+    pub fn new(list: HittableList) -> BvhNode {
+        BvhNode::from_objects(list.into_objects())
+    }
+
+    pub fn from_objects(mut objects: Vec<Arc<dyn Hittable>>) -> BvhNode {
+        match objects.len() {
+            // Pad with empty lists so small scenes still produce a valid node
+            0 => BvhNode::from_children(
+                Arc::new(HittableList::new()),
+                Arc::new(HittableList::new()),
+            ),
+            1 => BvhNode::from_children(objects.remove(0), Arc::new(HittableList::new())),
+            _ => BvhNode::split(objects),
+        }
+    }
+
+    fn build(mut objects: Vec<Arc<dyn Hittable>>) -> Arc<dyn Hittable> {
+        if objects.len() == 1 {
+            return objects.remove(0);
+        }
+        Arc::new(BvhNode::split(objects))
+    }
+
+    fn split(mut objects: Vec<Arc<dyn Hittable>>) -> BvhNode {
+        let bbox = objects
+            .iter()
+            .fold(Aabb::EMPTY, |acc, o| Aabb::enclosing(&acc, &o.bounding_box()));
+        let axis = bbox.longest_axis();
+
+        let mid = objects.len() / 2;
+        objects.select_nth_unstable_by(mid, |a, b| {
+            let a = a.bounding_box().centroid(axis);
+            let b = b.bounding_box().centroid(axis);
+            a.total_cmp(&b)
+        });
+        let right = objects.split_off(mid);
+
+        BvhNode::from_children(BvhNode::build(objects), BvhNode::build(right))
+    }
+
+    fn from_children(left: Arc<dyn Hittable>, right: Arc<dyn Hittable>) -> BvhNode {
+        let bbox = Aabb::enclosing(&left.bounding_box(), &right.bounding_box());
+        BvhNode { left, right, bbox }
+    }
+}
+
+impl Hittable for BvhNode {
+    fn hit<'a>(&'a self, ray: &Ray, ray_t: Interval, hit_record: &mut HitRecord<'a>) -> bool {
+        if !self.bbox.hit(ray, ray_t) {
+            return false;
+        }
+
+        let hit_left = self.left.hit(ray, ray_t, hit_record);
+        // Only look for hits on the right that are closer than the left one
+        let right_max = if hit_left { hit_record.t } else { ray_t.max };
+        let hit_right = self
+            .right
+            .hit(ray, Interval::new(ray_t.min, right_max), hit_record);
+
+        hit_left || hit_right
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::INFINITY;
+    use crate::material::Lambertian;
+    use crate::quad::Quad;
+    use crate::sphere::Sphere;
+    use crate::triangle::Triangle;
+    use crate::utils::{random_f64, random_f64_in_range, seeded_rng, Rng};
+    use crate::vector::{Point3, Vec3};
+
+    fn random_objects(count: usize, rng: &mut Rng) -> Vec<Arc<dyn Hittable>> {
+        (0..count)
+            .map(|i| -> Arc<dyn Hittable> {
+                let material = Arc::new(Lambertian::new(Vec3::random(rng)));
+                let center = Vec3::random_in_range(-3.0, 3.0, rng);
+                if i % 2 == 0 {
+                    let radius = random_f64_in_range(0.1, 1.0, rng);
+                    Arc::new(Sphere::new(center, radius, material))
+                } else {
+                    let a = Vec3::random_in_range(-1.0, 1.0, rng);
+                    let b = Vec3::random_in_range(-1.0, 1.0, rng);
+                    Arc::new(Triangle::new(
+                        center + a,
+                        center + b,
+                        center - a - b,
+                        material,
+                    ))
+                }
+            })
+            .collect()
+    }
+
+    // Concentric spheres and quads centred on the origin, so every bounding box has the same
+    // centroid and no split axis separates anything
+    fn objects_with_equal_centroids(count: usize, rng: &mut Rng) -> Vec<Arc<dyn Hittable>> {
+        (0..count)
+            .map(|i| -> Arc<dyn Hittable> {
+                let material = Arc::new(Lambertian::new(Vec3::random(rng)));
+                if i % 2 == 0 {
+                    let radius = random_f64_in_range(0.1, 4.0, rng);
+                    Arc::new(Sphere::new(Point3::default(), radius, material))
+                } else {
+                    let u = Vec3::random_in_range(-2.0, 2.0, rng);
+                    let v = Vec3::random_in_range(-2.0, 2.0, rng);
+                    Arc::new(Quad::new((u + v) * -0.5, u, v, material))
+                }
+            })
+            .collect()
+    }
+
+    // Checks that the BVH reports exactly the hits a linear scan of the same objects does
+    fn assert_matches_brute_force(objects: Vec<Arc<dyn Hittable>>, rng: &mut Rng) {
+        let mut list = HittableList::new();
+        for object in &objects {
+            list.add(object.clone());
+        }
+        let bvh = BvhNode::from_objects(objects.clone());
+
+        let mut hits = 0;
+        for _ in 0..2000 {
+            // Aim near one of the objects so most rays hit something
+            let object = &objects[(random_f64(rng) * objects.len() as f64) as usize];
+            let bbox = object.bounding_box();
+            let centroid = Point3::new(bbox.centroid(0), bbox.centroid(1), bbox.centroid(2));
+            let target = centroid + Vec3::random_in_range(-0.5, 0.5, rng);
+            let origin = Vec3::random_in_range(-8.0, 8.0, rng);
+            let ray = Ray::new(origin, target - origin);
+            let ray_t = Interval::new(0.001, INFINITY);
+
+            let mut expected = HitRecord::default();
+            let mut actual = HitRecord::default();
+            let hit = list.hit(&ray, ray_t, &mut expected);
+            assert_eq!(bvh.hit(&ray, ray_t, &mut actual), hit);
+            if hit {
+                hits += 1;
+                assert_eq!(actual.t, expected.t);
+                assert_eq!(actual.p, expected.p);
+                assert_eq!(actual.normal, expected.normal);
+                assert!(std::ptr::addr_eq(actual.material, expected.material));
+            }
+        }
+        assert!(hits > 500, "only {} of the rays hit anything", hits);
+    }
+
+    #[test]
+    fn hits_match_brute_force() {
+        let mut rng = seeded_rng(1, 0);
+        for count in [1, 2, 3, 10, 100] {
+            let objects = random_objects(count, &mut rng);
+            assert_matches_brute_force(objects, &mut rng);
+        }
+    }
+
+    #[test]
+    fn hits_match_brute_force_with_equal_centroids() {
+        let mut rng = seeded_rng(2, 0);
+        let objects = objects_with_equal_centroids(40, &mut rng);
+        for object in &objects {
+            for axis in 0..3 {
+                assert!(object.bounding_box().centroid(axis).abs() < 1e-12);
+            }
+        }
+        assert_matches_brute_force(objects, &mut rng);
+    }
+
+    #[test]
+    fn empty_bvh_misses() {
+        let bvh = BvhNode::from_objects(vec![]);
+        let ray = Ray::new(Point3::default(), Vec3::new(0.0, 0.0, -1.0));
+        let mut rec = HitRecord::default();
+        assert!(!bvh.hit(&ray, Interval::new(0.001, INFINITY), &mut rec));
+    }
+}
@@ -1,11 +1,18 @@
+use crate::background::Background;
 use crate::color::Color;
-use crate::hittable::Hittable;
+use crate::film::{Film, FilmTile};
+use crate::filter::Filter;
+use crate::framebuffer::{FrameBuffer, Tile};
+use crate::hittable::{Hittable, HittableList};
+use crate::parallel::for_each_stealing;
 use crate::ray::Ray;
+use crate::sampler::{Sampler, SamplerKind};
+use crate::spectrum::{sample_wavelength, wavelength_to_rgb};
 use crate::utils::degrees_to_radians;
-use crate::utils::sample_square;
 use crate::vector::{Point3, Vec3};
 
-use parallel_executor::parallel_iterator::ParallelIterator;
+use std::ops::ControlFlow;
+use std::thread;
 
 #[derive(Debug, Default, Clone, Copy)]
 pub struct Camera {
@@ -19,11 +26,26 @@ pub struct Camera {
     pub vup: Vec3,
     pub defocus_angle: f64,
     pub focus_dist: f64,
+    pub background: Background,
+    pub seed: u64, // Renders with the same seed are identical
+    pub sampler: SamplerKind,
+    pub filter: Filter, // Weights each sample into the pixels around it
+    // Trace a single wavelength per path so dispersive materials split light into colours. The
+    // paths still carry RGB, filtered through the colour of their wavelength.
+    pub spectral: bool,
+
+    // With a threshold above zero `render` stops sampling a pixel once the 95% confidence
+    // interval of its brightness, measured after gamma correction, is narrower than the threshold
+    // either side. Pixels still get at least `min_samples_per_pixel` samples and at most
+    // `samples_per_pixel`. Progressive renders always sample every pixel.
+    pub adaptive_threshold: f64,
+    pub min_samples_per_pixel: u32,
 
     pub multithreaded: bool,
+    pub threads: usize, // 0 uses one thread per core
+    pub tile_size: u32, // Width and height of the square tiles handed to threads
 
     image_height: u32,
-    pixel_sample_scale: f64,
     center: Point3,
     pixel00_loc: Point3,
     pixel_delta_u: Vec3,
@@ -37,89 +59,256 @@ pub struct Camera {
 
 impl Camera {
     pub fn new() -> Self {
-        let mut c = Camera::default();
-        c.aspect_ratio = 1.0;
-        c.image_width = 100;
-        c.max_depth = 10;
-        c.look_from = Point3::new(0.0, 0.0, 0.0);
-        c.look_at = Point3::new(0.0, 0.0, -1.0);
-        c.vup = Vec3::new(0.0, 1.0, 0.0);
-        c
-    }
-    pub fn render<T>(mut self, world: T)
+        Camera {
+            aspect_ratio: 1.0,
+            image_width: 100,
+            samples_per_pixel: 10,
+            max_depth: 10,
+            vfov: 90.0,
+            look_from: Point3::new(0.0, 0.0, 0.0),
+            look_at: Point3::new(0.0, 0.0, -1.0),
+            vup: Vec3::new(0.0, 1.0, 0.0),
+            focus_dist: 10.0,
+            min_samples_per_pixel: 16,
+            tile_size: 16,
+            ..Default::default()
+        }
+    }
+    // Lights are the emissive objects worth sampling directly, pass an empty list to rely on
+    // rays finding them by chance
+    pub fn render<T>(self, world: T, lights: &HittableList) -> FrameBuffer
+    where
+        T: Hittable,
+    {
+        self.render_with_heatmap(world, lights).0
+    }
+
+    // Render, also returning a heatmap of the samples spent on each pixel, from blue for
+    // `min_samples_per_pixel` or fewer to red for `samples_per_pixel`
+    pub fn render_with_heatmap<T>(
+        mut self,
+        world: T,
+        lights: &HittableList,
+    ) -> (FrameBuffer, FrameBuffer)
     where
-        T: Hittable + Send + Clone + 'static, // Clone for each thread, Send for parallel execution
+        T: Hittable,
     {
         self.initialize();
 
-        // Print header
-        println!("P3\n{} {}\n255", self.image_width, self.image_height);
-
-        if self.multithreaded {
-            // Flatten the 2D loop into a 1D iterator
-            let pixel_indices: Vec<(u32, u32)> = (0..self.image_height)
-                .flat_map(|j| (0..self.image_width).map(move |i| (i, j)))
-                .collect();
-
-            // Parallel processing of pixels
-            let mut colors: Vec<(u32, u32, String)> =
-                pixel_indices.into_iter().par_map(100, move |(i, j)| {
-                    let thread_renderer = self.clone(); // Clone renderer for each thread
-                    let thread_world = world.clone(); // Clone world for each thread
-
-                    let mut pixel_color = Color::new(0.0, 0.0, 0.0);
-                    for _ in 0..thread_renderer.samples_per_pixel {
-                        let ray = thread_renderer.get_ray(i, j);
-                        pixel_color += ray.color(thread_renderer.max_depth.clone(), &thread_world);
-                    }
-                    //eprintln!("processing row {}", j);
-                    (
-                        i,
-                        j,
-                        (pixel_color * thread_renderer.pixel_sample_scale).to_string(),
-                    )
-                });
-
-            // Sort by (j, i) to ensure correct row-wise order
-            colors.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(&b.0)));
-
-            // Print the colors in order
-            for (_, _, color) in colors {
-                print!("{}", color);
-            }
+        let mut film = Film::new(self.image_width, self.image_height, self.filter);
+        let mut heatmap = FrameBuffer::new(self.image_width, self.image_height);
+
+        let threads = if self.multithreaded {
+            self.thread_count()
         } else {
-            for j in 0..self.image_height {
-                for i in 0..self.image_width {
-                    let mut pixel_color = Color::new(0.0, 0.0, 0.0);
-                    for _ in 0..self.samples_per_pixel {
-                        let ray = self.get_ray(i, j);
-                        pixel_color += ray.color(self.max_depth.clone(), &world);
-                    }
-                    print!("{}", (pixel_color * self.pixel_sample_scale).to_string());
-                }
+            1
+        };
+        let mut tiles = film.tiles(self.tile_size);
+        let work = tiles
+            .iter_mut()
+            .zip(heatmap.tiles_mut(self.tile_size))
+            .collect();
+        for_each_stealing(work, threads, |(tile, mut heat)| {
+            self.render_tile(tile, &mut heat, &world, lights)
+        });
+        // Tiles overlap once the filter is wider than a pixel, adding them in order keeps the sums
+        // the same whichever thread rendered them
+        for tile in &tiles {
+            film.add_tile(tile);
+        }
+
+        (film.resolve(), heatmap)
+    }
+
+    // Render one sample per pixel per pass, summing into an accumulation buffer. After every pass
+    // `on_pass` gets the number of passes done and the average so far, and can break to stop
+    // early. With the box filter the finished image is identical to what `render` gives, wider
+    // filters add the samples in a different order so pixels can differ by rounding.
+    pub fn render_progressive<T, F>(
+        mut self,
+        world: T,
+        lights: &HittableList,
+        mut on_pass: F,
+    ) -> FrameBuffer
+    where
+        T: Hittable,
+        F: FnMut(u32, &FrameBuffer) -> ControlFlow<()>,
+    {
+        self.initialize();
+
+        let mut film = Film::new(self.image_width, self.image_height, self.filter);
+        let mut image = FrameBuffer::new(self.image_width, self.image_height);
+
+        let threads = if self.multithreaded {
+            self.thread_count()
+        } else {
+            1
+        };
+        for pass in 0..self.samples_per_pixel {
+            let mut tiles = film.tiles(self.tile_size);
+            for_each_stealing(tiles.iter_mut().collect(), threads, |tile| {
+                self.accumulate_tile(tile, pass, &world, lights)
+            });
+            for tile in &tiles {
+                film.add_tile(tile);
+            }
+
+            image = film.resolve();
+            if on_pass(pass + 1, &image).is_break() {
+                break;
+            }
+        }
+
+        image
+    }
+
+    fn thread_count(&self) -> usize {
+        if self.threads > 0 {
+            return self.threads;
+        }
+        thread::available_parallelism().map_or(1, |n| n.get())
+    }
+
+    fn render_tile<T: Hittable>(
+        &self,
+        tile: &mut FilmTile,
+        heat: &mut Tile,
+        world: &T,
+        lights: &HittableList,
+    ) {
+        let min_samples = self.min_samples_per_pixel.min(self.samples_per_pixel);
+        let range = (self.samples_per_pixel - min_samples).max(1) as f64;
+        for j in 0..tile.height() {
+            for i in 0..tile.width() {
+                let samples = self.sample_pixel(tile, tile.x() + i, tile.y() + j, world, lights);
+                let used = samples.saturating_sub(min_samples) as f64 / range;
+                heat.set_pixel(i, j, heat_color(used));
+            }
+        }
+    }
+
+    fn accumulate_tile<T: Hittable>(
+        &self,
+        tile: &mut FilmTile,
+        sample: u32,
+        world: &T,
+        lights: &HittableList,
+    ) {
+        for j in 0..tile.height() {
+            for i in 0..tile.width() {
+                let (x, y, color) = self.sample(tile.x() + i, tile.y() + j, sample, world, lights);
+                tile.add_sample(x, y, color);
+            }
+        }
+    }
+
+    // Splat the samples of pixel i, j into the tile, returning how many it took
+    fn sample_pixel<T: Hittable>(
+        &self,
+        tile: &mut FilmTile,
+        i: u32,
+        j: u32,
+        world: &T,
+        lights: &HittableList,
+    ) -> u32 {
+        // Running mean and sum of squared deviations of the luminance (Welford's method)
+        let mut mean = 0.0;
+        let mut m2 = 0.0;
+
+        let mut samples = 0;
+        while samples < self.samples_per_pixel {
+            let (x, y, color) = self.sample(i, j, samples, world, lights);
+            tile.add_sample(x, y, color);
+            samples += 1;
+
+            let luminance = color.luminance();
+            let delta = luminance - mean;
+            mean += delta / samples as f64;
+            m2 += delta * (luminance - mean);
+
+            if self.adaptive_threshold > 0.0
+                && samples >= self.min_samples_per_pixel.max(2)
+                && self.converged(samples, mean, m2)
+            {
+                break;
             }
         }
+        samples
+    }
+
+    fn converged(&self, samples: u32, mean: f64, m2: f64) -> bool {
+        let variance = m2 / (samples - 1) as f64;
+        if variance <= 0.0 {
+            return true;
+        }
+        if mean <= 0.0 {
+            return false;
+        }
+        // Gamma correction takes the square root, which scales small errors by 1 / (2 sqrt(mean))
+        let half_width = 1.96 * (variance / samples as f64).sqrt();
+        half_width / (2.0 * mean.sqrt()) <= self.adaptive_threshold
+    }
+
+    // Position on the image of one sample of pixel i, j and the light arriving there
+    fn sample<T: Hittable>(
+        &self,
+        i: u32,
+        j: u32,
+        sample: u32,
+        world: &T,
+        lights: &HittableList,
+    ) -> (f64, f64, Color) {
+        let pixel = j as u64 * self.image_width as u64 + i as u64;
+        let mut sampler = Sampler::new(
+            self.sampler,
+            self.samples_per_pixel,
+            self.seed,
+            pixel,
+            sample,
+        );
+
+        // Pixel i, j covers i to i + 1 across and j to j + 1 down
+        let (offset_x, offset_y) = sampler.get_2d();
+        let (x, y) = (i as f64 + offset_x, j as f64 + offset_y);
+        let mut ray = self.get_ray(x, y, &mut sampler);
+        // Averaged over the wavelengths these colours add up to white, so materials without
+        // dispersion look the same as in RGB renders
+        let mut response = Color::new(1.0, 1.0, 1.0);
+        if self.spectral {
+            let (wavelength, pdf) = sample_wavelength(sampler.get_1d());
+            ray = ray.with_wavelength(Some(wavelength));
+            response = wavelength_to_rgb(wavelength) / pdf;
+        }
+        let color = ray.color(
+            self.max_depth,
+            world,
+            lights,
+            &self.background,
+            &mut sampler,
+        );
+        (x, y, color * response)
     }
 
-    fn get_ray(&self, i: u32, j: u32) -> Ray {
-        // Construct a ray from the camera's center point shooting towards a randomly sampled point
-        // near i,j
+    fn get_ray(&self, x: f64, y: f64, sampler: &mut Sampler) -> Ray {
+        // Construct a ray from the camera's center point shooting towards the point x, y of the
+        // image
 
-        let offset = sample_square();
-        let pixel_sample = self.pixel00_loc
-            + (self.pixel_delta_u * (i as f64 + offset.x()))
-            + (self.pixel_delta_v * (j as f64 + offset.y()));
+        // pixel00_loc is the centre of the first pixel, half a pixel in from the corner
+        let pixel_sample =
+            self.pixel00_loc + (self.pixel_delta_u * (x - 0.5)) + (self.pixel_delta_v * (y - 0.5));
+        // The lens sample is drawn even without defocus so later dimensions line up the same way
+        let lens_sample = sampler.get_2d();
         let ray_origin = if self.defocus_angle <= 0.0 {
-            self.center.clone()
+            self.center
         } else {
-            self.defocus_disk_sample()
+            self.defocus_disk_sample(lens_sample)
         };
-        let ray_direction = &pixel_sample - &ray_origin;
+        let ray_direction = pixel_sample - ray_origin;
         Ray::new(ray_origin, ray_direction)
     }
 
-    fn defocus_disk_sample(&self) -> Point3 {
-        let p = Vec3::random_in_unit_disk();
+    fn defocus_disk_sample(&self, lens_sample: (f64, f64)) -> Point3 {
+        let p = Vec3::sample_unit_disk(lens_sample);
         self.center + (self.defocus_disk_u * p.x()) + (self.defocus_disk_v * p.y())
     }
 
@@ -127,8 +316,6 @@ impl Camera {
         let image_height: u32 = (self.image_width as f64 / self.aspect_ratio).floor() as u32;
         self.image_height = image_height.max(1);
 
-        self.pixel_sample_scale = 1.0 / (self.samples_per_pixel as f64);
-
         self.center = self.look_from;
 
         let theta = degrees_to_radians(self.vfov);
@@ -147,18 +334,190 @@ impl Camera {
         let viewport_v = self.v * viewport_height * -1.0;
 
         // Calculate horizontal and vertical delta vectors for each pixel
-        self.pixel_delta_u = &viewport_u / self.image_width as f64;
-        self.pixel_delta_v = &viewport_v / self.image_height as f64;
+        self.pixel_delta_u = viewport_u / self.image_width as f64;
+        self.pixel_delta_v = viewport_v / self.image_height as f64;
 
         // Calculate location of upper left corner pixel
         let viewport_upper_left =
-            &self.center - &(&self.w * self.focus_dist) - &viewport_u / 2.0 - &viewport_v / 2.0;
+            self.center - (self.w * self.focus_dist) - viewport_u / 2.0 - viewport_v / 2.0;
 
-        self.pixel00_loc =
-            &viewport_upper_left + &((&self.pixel_delta_u + &self.pixel_delta_v) * 0.5);
+        self.pixel00_loc = viewport_upper_left + (self.pixel_delta_u + self.pixel_delta_v) * 0.5;
 
         let defocus_radius = self.focus_dist * degrees_to_radians(self.defocus_angle / 2.0);
         self.defocus_disk_u = self.u * defocus_radius;
         self.defocus_disk_v = self.v * defocus_radius;
     }
 }
+
+// Blue through green to red as `t` goes from 0 to 1
+fn heat_color(t: f64) -> Color {
+    let blue = Color::new(0.0, 0.0, 1.0);
+    let green = Color::new(0.0, 1.0, 0.0);
+    let red = Color::new(1.0, 0.0, 0.0);
+    let t = t.clamp(0.0, 1.0);
+    if t < 0.5 {
+        blue * (1.0 - 2.0 * t) + green * (2.0 * t)
+    } else {
+        green * (2.0 - 2.0 * t) + red * (2.0 * t - 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Dielectric, DiffuseLight, Lambertian, Metal};
+    use crate::quad::Quad;
+    use crate::sphere::Sphere;
+    use std::sync::Arc;
+
+    // A little of everything: diffuse, mirror and glass spheres under a sampled light
+    fn scene() -> (HittableList, HittableList) {
+        let mut world = HittableList::new();
+        let ground = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        world.add(Arc::new(Sphere::new(
+            Point3::new(0.0, -100.5, -1.0),
+            100.0,
+            ground,
+        )));
+        world.add(Arc::new(Sphere::new(
+            Point3::new(-1.0, 0.0, -1.0),
+            0.5,
+            Arc::new(Dielectric::rough(1.5, 0.2)),
+        )));
+        world.add(Arc::new(Sphere::new(
+            Point3::new(0.0, 0.0, -1.2),
+            0.5,
+            Arc::new(Lambertian::new(Color::new(0.1, 0.2, 0.5))),
+        )));
+        world.add(Arc::new(Sphere::new(
+            Point3::new(1.0, 0.0, -1.0),
+            0.5,
+            Arc::new(Metal::new(Color::new(0.8, 0.6, 0.2), 0.3)),
+        )));
+        let light = Arc::new(Quad::new(
+            Point3::new(-0.5, 1.5, -1.5),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Arc::new(DiffuseLight::new(Color::new(4.0, 4.0, 4.0))),
+        ));
+        world.add(light.clone());
+        let mut lights = HittableList::new();
+        lights.add(light);
+        (world, lights)
+    }
+
+    fn camera(threads: usize, tile_size: u32) -> Camera {
+        let mut camera = Camera::new();
+        camera.aspect_ratio = 1.5;
+        camera.image_width = 24;
+        camera.samples_per_pixel = 12;
+        camera.max_depth = 6;
+        camera.seed = 7;
+        camera.sampler = SamplerKind::Sobol;
+        camera.filter = Filter::MITCHELL;
+        camera.multithreaded = threads != 1;
+        camera.threads = threads;
+        camera.tile_size = tile_size;
+        camera
+    }
+
+    #[test]
+    fn renders_are_identical_for_any_thread_count() {
+        let (world, lights) = scene();
+        for tile_size in [3, 8, 64] {
+            let single = camera(1, tile_size).render(world.clone(), &lights);
+            for threads in [2, 5] {
+                let multi = camera(threads, tile_size).render(world.clone(), &lights);
+                assert!(
+                    single.pixels() == multi.pixels(),
+                    "{} threads, {} pixel tiles",
+                    threads,
+                    tile_size
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn adaptive_renders_are_identical_for_any_thread_count() {
+        let (world, lights) = scene();
+        let adaptive = |threads| {
+            let mut camera = camera(threads, 5);
+            camera.samples_per_pixel = 32;
+            camera.min_samples_per_pixel = 4;
+            camera.adaptive_threshold = 0.05;
+            camera.render_with_heatmap(world.clone(), &lights)
+        };
+        let (image, heatmap) = adaptive(1);
+        for threads in [2, 5] {
+            let (other_image, other_heatmap) = adaptive(threads);
+            assert!(
+                image.pixels() == other_image.pixels(),
+                "{} threads",
+                threads
+            );
+            assert!(
+                heatmap.pixels() == other_heatmap.pixels(),
+                "{} threads",
+                threads
+            );
+        }
+    }
+
+    #[test]
+    fn progressive_renders_are_identical_for_any_thread_count() {
+        let (world, lights) = scene();
+        let progressive = |threads| {
+            camera(threads, 5)
+                .render_progressive(world.clone(), &lights, |_, _| ControlFlow::Continue(()))
+        };
+        let single = progressive(1);
+        for threads in [2, 5] {
+            assert!(
+                single.pixels() == progressive(threads).pixels(),
+                "{} threads",
+                threads
+            );
+        }
+    }
+
+    #[test]
+    fn samples_stay_within_half_a_pixel_of_the_centre() {
+        let empty = HittableList::new();
+        for kind in [
+            SamplerKind::Independent,
+            SamplerKind::Stratified,
+            SamplerKind::Halton,
+            SamplerKind::Sobol,
+        ] {
+            let mut camera = camera(1, 8);
+            camera.sampler = kind;
+            camera.samples_per_pixel = 64;
+            camera.initialize();
+            for (i, j) in [(0, 0), (7, 4), (23, 15)] {
+                let (mut mean_x, mut mean_y) = (0.0, 0.0);
+                for sample in 0..64 {
+                    let (x, y, _) = camera.sample(i, j, sample, &empty, &empty);
+                    let offset_x = x - (i as f64 + 0.5);
+                    let offset_y = y - (j as f64 + 0.5);
+                    assert!((-0.5..0.5).contains(&offset_x), "{:?}: {}", kind, offset_x);
+                    assert!((-0.5..0.5).contains(&offset_y), "{:?}: {}", kind, offset_y);
+                    mean_x += offset_x / 64.0;
+                    mean_y += offset_y / 64.0;
+                }
+                // Centred on the pixel, not on its corner
+                assert!(mean_x.abs() < 0.15 && mean_y.abs() < 0.15, "{:?}", kind);
+            }
+        }
+    }
+
+    #[test]
+    fn pixel_centre_maps_to_pixel_location() {
+        let mut camera = camera(1, 8);
+        camera.initialize();
+        let mut sampler = Sampler::new(SamplerKind::Independent, 1, 0, 0, 0);
+        let ray = camera.get_ray(3.5, 2.5, &mut sampler);
+        let expected = camera.pixel00_loc + camera.pixel_delta_u * 3.0 + camera.pixel_delta_v * 2.0;
+        assert!((*ray.origin() + *ray.direction() - expected).length() < 1e-12);
+    }
+}
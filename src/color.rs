@@ -3,15 +3,28 @@ use crate::{interval::Interval, utils::linear_to_gamma, vector::Vec3};
 pub type Color = Vec3;
 
 impl Color {
+    #[allow(clippy::inherent_to_string)]
     pub fn to_string(&self) -> String {
+        let [rbyte, gbyte, bbyte] = self.to_bytes();
+        format!("{} {} {}\n", rbyte, gbyte, bbyte)
+    }
+
+    // Gamma corrected and clamped to 8 bits per channel
+    pub fn to_bytes(&self) -> [u8; 3] {
         let intensity = Interval::new(0.0, 1.0);
         let r = linear_to_gamma(self.x());
         let g = linear_to_gamma(self.y());
         let b = linear_to_gamma(self.z());
 
-        let rbyte = (255.0 * intensity.clamp(r)) as u32;
-        let gbyte = (255.0 * intensity.clamp(g)) as u32;
-        let bbyte = (255.0 * intensity.clamp(b)) as u32;
-        format!("{} {} {}\n", rbyte, gbyte, bbyte)
+        [
+            (255.0 * intensity.clamp(r)) as u8,
+            (255.0 * intensity.clamp(g)) as u8,
+            (255.0 * intensity.clamp(b)) as u8,
+        ]
+    }
+
+    // Relative luminance with Rec. 709 primaries
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.x() + 0.7152 * self.y() + 0.0722 * self.z()
     }
 }
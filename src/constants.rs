@@ -1,3 +1,3 @@
 pub use std::f64::consts::PI;
-pub use std::f64::INFINITY;
-pub use std::f64::NEG_INFINITY;
+pub const INFINITY: f64 = f64::INFINITY;
+pub const NEG_INFINITY: f64 = f64::NEG_INFINITY;
@@ -0,0 +1,273 @@
+use crate::color::Color;
+use crate::filter::Filter;
+use crate::framebuffer::{tile_rects, FrameBuffer};
+
+// Filter weighted sums of the samples landing near each pixel of a rectangle of the image. The
+// film of the whole image starts at 0, 0. Tiles splat into films of their own, padded by the
+// filter radius, which are added into the whole image once the tile is done.
+#[derive(Debug, Clone)]
+pub struct Film {
+    x: i64,
+    y: i64,
+    width: u32,
+    height: u32,
+    filter: Filter,
+    sums: Vec<Color>,
+    weights: Vec<f64>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32, filter: Filter) -> Film {
+        Film::region(0, 0, width, height, filter)
+    }
+
+    fn region(x: i64, y: i64, width: u32, height: u32, filter: Filter) -> Film {
+        let len = width as usize * height as usize;
+        Film {
+            x,
+            y,
+            width,
+            height,
+            filter,
+            sums: vec![Color::default(); len],
+            weights: vec![0.0; len],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    // Split the image into the same tiles as FrameBuffer::tiles_mut, each with an empty film
+    // covering every pixel its samples can reach
+    pub fn tiles(&self, tile_size: u32) -> Vec<FilmTile> {
+        // Samples lie within their own pixel, so they reach pixel centres up to the radius away
+        let pad = (self.filter.radius() - 0.5).max(0.0).ceil() as i64;
+        tile_rects(self.width, self.height, tile_size)
+            .into_iter()
+            .map(|(x, y, width, height)| {
+                let left = (self.x + x as i64 - pad).max(self.x);
+                let top = (self.y + y as i64 - pad).max(self.y);
+                let right = (self.x + (x + width) as i64 + pad).min(self.x + self.width as i64);
+                let bottom = (self.y + (y + height) as i64 + pad).min(self.y + self.height as i64);
+                FilmTile {
+                    x,
+                    y,
+                    width,
+                    height,
+                    film: Film::region(
+                        left,
+                        top,
+                        (right - left) as u32,
+                        (bottom - top) as u32,
+                        self.filter,
+                    ),
+                }
+            })
+            .collect()
+    }
+
+    // Add a sample at continuous image coordinates `x`, `y` to every pixel of the film whose
+    // centre is within the filter radius
+    pub fn add_sample(&mut self, x: f64, y: f64, color: Color) {
+        let radius = self.filter.radius();
+        // Pixel i covers i to i + 1, so its centre is within reach when x - radius - 0.5 < i <=
+        // x + radius - 0.5
+        let first_i = ((x - radius - 0.5).floor() as i64 + 1).max(self.x);
+        let last_i = ((x + radius - 0.5).floor() as i64).min(self.x + self.width as i64 - 1);
+        let first_j = ((y - radius - 0.5).floor() as i64 + 1).max(self.y);
+        let last_j = ((y + radius - 0.5).floor() as i64).min(self.y + self.height as i64 - 1);
+
+        for j in first_j..=last_j {
+            for i in first_i..=last_i {
+                let weight = self.filter.evaluate(x - i as f64 - 0.5, y - j as f64 - 0.5);
+                if weight == 0.0 {
+                    continue;
+                }
+                let index = self.index(i, j);
+                self.sums[index] += color * weight;
+                self.weights[index] += weight;
+            }
+        }
+    }
+
+    // Add the sums of a tile's film to the pixels the two films share
+    pub fn add_tile(&mut self, tile: &FilmTile) {
+        let other = &tile.film;
+        let first_i = other.x.max(self.x);
+        let last_i = (other.x + other.width as i64).min(self.x + self.width as i64);
+        let first_j = other.y.max(self.y);
+        let last_j = (other.y + other.height as i64).min(self.y + self.height as i64);
+
+        for j in first_j..last_j {
+            for i in first_i..last_i {
+                let (index, other_index) = (self.index(i, j), other.index(i, j));
+                self.sums[index] += other.sums[other_index];
+                self.weights[index] += other.weights[other_index];
+            }
+        }
+    }
+
+    // Weighted average of every pixel. Pixels without any weight are black. Filters with
+    // negative lobes can leave a pixel with a negative total, which still divides out fine.
+    pub fn resolve(&self) -> FrameBuffer {
+        let mut image = FrameBuffer::new(self.width, self.height);
+        for ((pixel, sum), weight) in image
+            .pixels_mut()
+            .iter_mut()
+            .zip(&self.sums)
+            .zip(&self.weights)
+        {
+            if *weight != 0.0 {
+                *pixel = *sum / *weight;
+            }
+        }
+        image
+    }
+
+    fn index(&self, i: i64, j: i64) -> usize {
+        ((j - self.y) * self.width as i64 + (i - self.x)) as usize
+    }
+}
+
+// Tile of pixels to sample, with the film its samples are splatted into
+#[derive(Debug)]
+pub struct FilmTile {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    film: Film,
+}
+
+impl FilmTile {
+    // Position of the top left pixel in the whole image
+    pub fn x(&self) -> u32 {
+        self.x
+    }
+
+    pub fn y(&self) -> u32 {
+        self.y
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    // Sample at continuous coordinates of the whole image
+    pub fn add_sample(&mut self, x: f64, y: f64, color: Color) {
+        self.film.add_sample(x, y, color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILTERS: [Filter; 5] = [
+        Filter::BOX,
+        Filter::TENT,
+        Filter::GAUSSIAN,
+        Filter::MITCHELL,
+        Filter::LANCZOS,
+    ];
+
+    fn assert_close(actual: Color, expected: Color) {
+        assert!(
+            (actual - expected).length() < 1e-9,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    // Jittered samples covering every pixel of the image, in a fixed order
+    fn samples(width: u32, height: u32) -> Vec<(f64, f64)> {
+        let mut rng = crate::utils::seeded_rng(4, 0);
+        let mut samples = vec![];
+        for j in 0..height {
+            for i in 0..width {
+                for _ in 0..16 {
+                    let offset = crate::utils::sample_square(&mut rng);
+                    samples.push((i as f64 + 0.5 + offset.x(), j as f64 + 0.5 + offset.y()));
+                }
+            }
+        }
+        samples
+    }
+
+    #[test]
+    fn constant_image_is_unchanged() {
+        let color = Color::new(0.2, 0.5, 3.0);
+        for filter in FILTERS {
+            let mut film = Film::new(7, 5, filter);
+            for (x, y) in samples(7, 5) {
+                film.add_sample(x, y, color);
+            }
+            for pixel in film.resolve().pixels() {
+                assert_close(*pixel, color);
+            }
+        }
+    }
+
+    #[test]
+    fn tiled_film_matches_whole_film() {
+        for filter in FILTERS {
+            let mut whole = Film::new(7, 5, filter);
+            let mut tiled = Film::new(7, 5, filter);
+            let mut tiles = tiled.tiles(3);
+            for (n, (x, y)) in samples(7, 5).into_iter().enumerate() {
+                let color = Color::new(x, y, n as f64);
+                whole.add_sample(x, y, color);
+                let tile = tiles
+                    .iter_mut()
+                    .find(|t| {
+                        let (i, j) = (x as u32, y as u32);
+                        i >= t.x() && i < t.x() + t.width() && j >= t.y() && j < t.y() + t.height()
+                    })
+                    .unwrap();
+                tile.add_sample(x, y, color);
+            }
+            for tile in &tiles {
+                tiled.add_tile(tile);
+            }
+            for (a, b) in whole
+                .resolve()
+                .pixels()
+                .iter()
+                .zip(tiled.resolve().pixels())
+            {
+                assert_close(*a, *b);
+            }
+        }
+    }
+
+    #[test]
+    fn negative_weight_totals_are_divided_out() {
+        // A lone sample one and a half pixels away sits in a negative lobe of the Lanczos filter
+        let mut film = Film::new(3, 1, Filter::LANCZOS);
+        let color = Color::new(0.25, 0.5, 1.0);
+        film.add_sample(2.0, 0.5, color);
+        assert!(film.weights[0] < 0.0);
+        for pixel in film.resolve().pixels() {
+            assert_close(*pixel, color);
+        }
+    }
+
+    #[test]
+    fn pixels_without_samples_are_black() {
+        let mut film = Film::new(8, 1, Filter::BOX);
+        film.add_sample(0.5, 0.5, Color::new(1.0, 1.0, 1.0));
+        let image = film.resolve();
+        assert_close(image.get_pixel(0, 0), Color::new(1.0, 1.0, 1.0));
+        assert_close(image.get_pixel(7, 0), Color::default());
+    }
+}
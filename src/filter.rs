@@ -0,0 +1,175 @@
+use crate::constants::PI;
+
+// Pixel reconstruction filter. Samples are spread over a square reaching `radius` pixels either
+// side of the pixel centre, and every sample counts towards each pixel whose centre lies within
+// the radius, weighted by the filter at its offset from that centre.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    // Equal weight everywhere, a radius of 0.5 averages each pixel's own square
+    Box { radius: f64 },
+    // Weight falling linearly to zero at the radius
+    Tent { radius: f64 },
+    // Gaussian shifted down so it reaches zero at the radius
+    Gaussian { radius: f64, sigma: f64 },
+    // Mitchell-Netravali cubic stretched over the radius, sharper than a Gaussian with slight
+    // negative lobes
+    Mitchell { radius: f64, b: f64, c: f64 },
+    // Sinc windowed by a wider sinc, the sharpest and the most prone to ringing
+    Lanczos { radius: f64 },
+}
+
+impl Default for Filter {
+    // Plain average of the samples in each pixel
+    fn default() -> Self {
+        Filter::BOX
+    }
+}
+
+impl Filter {
+    pub const BOX: Filter = Filter::Box { radius: 0.5 };
+    pub const TENT: Filter = Filter::Tent { radius: 1.0 };
+    pub const GAUSSIAN: Filter = Filter::Gaussian {
+        radius: 1.5,
+        sigma: 0.5,
+    };
+    pub const MITCHELL: Filter = Filter::Mitchell {
+        radius: 2.0,
+        b: 1.0 / 3.0,
+        c: 1.0 / 3.0,
+    };
+    pub const LANCZOS: Filter = Filter::Lanczos { radius: 3.0 };
+
+    pub fn radius(&self) -> f64 {
+        match *self {
+            Filter::Box { radius }
+            | Filter::Tent { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::Mitchell { radius, .. }
+            | Filter::Lanczos { radius } => radius,
+        }
+    }
+
+    // The same filter stretched or squeezed to another radius
+    pub fn with_radius(self, radius: f64) -> Filter {
+        match self {
+            Filter::Box { .. } => Filter::Box { radius },
+            Filter::Tent { .. } => Filter::Tent { radius },
+            Filter::Gaussian { sigma, .. } => Filter::Gaussian { radius, sigma },
+            Filter::Mitchell { b, c, .. } => Filter::Mitchell { radius, b, c },
+            Filter::Lanczos { .. } => Filter::Lanczos { radius },
+        }
+    }
+
+    // Weight of a sample `x`, `y` pixels away from a pixel centre. The support is half open, so
+    // a box filter of radius 0.5 gives a sample on the edge between two pixels to only one.
+    pub fn evaluate(&self, x: f64, y: f64) -> f64 {
+        let radius = self.radius();
+        if x < -radius || x >= radius || y < -radius || y >= radius {
+            return 0.0;
+        }
+        self.evaluate_1d(x, radius) * self.evaluate_1d(y, radius)
+    }
+
+    fn evaluate_1d(&self, x: f64, radius: f64) -> f64 {
+        let x = x.abs();
+        match *self {
+            Filter::Box { .. } => 1.0,
+            Filter::Tent { .. } => (radius - x).max(0.0),
+            Filter::Gaussian { sigma, .. } => {
+                let gaussian = |x: f64| (-x * x / (2.0 * sigma * sigma)).exp();
+                (gaussian(x) - gaussian(radius)).max(0.0)
+            }
+            Filter::Mitchell { b, c, .. } => mitchell(2.0 * x / radius, b, c),
+            Filter::Lanczos { .. } => sinc(x) * sinc(x / radius),
+        }
+    }
+}
+
+// Mitchell-Netravali cubic, which is zero from |x| = 2 onwards
+fn mitchell(x: f64, b: f64, c: f64) -> f64 {
+    let x = x.abs();
+    if x >= 2.0 {
+        0.0
+    } else if x > 1.0 {
+        ((-b - 6.0 * c) * x * x * x
+            + (6.0 * b + 30.0 * c) * x * x
+            + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c))
+            / 6.0
+    } else {
+        ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+            + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+            + (6.0 - 2.0 * b))
+            / 6.0
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        return 1.0;
+    }
+    (PI * x).sin() / (PI * x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Midpoint rule over the square the filter covers
+    fn integral(filter: Filter) -> f64 {
+        let radius = filter.radius();
+        let n = 400;
+        let step = 2.0 * radius / n as f64;
+        let mut total = 0.0;
+        for j in 0..n {
+            for i in 0..n {
+                let x = -radius + (i as f64 + 0.5) * step;
+                let y = -radius + (j as f64 + 0.5) * step;
+                total += filter.evaluate(x, y);
+            }
+        }
+        total * step * step
+    }
+
+    #[test]
+    fn weights_integrate_to_the_expected_volume() {
+        // Box: the square itself; tent: radius squared per axis
+        assert!((integral(Filter::BOX) - 1.0).abs() < 1e-9);
+        assert!((integral(Filter::Box { radius: 1.5 }) - 9.0).abs() < 1e-9);
+        assert!((integral(Filter::TENT) - 1.0).abs() < 1e-4);
+        assert!((integral(Filter::Tent { radius: 2.0 }) - 16.0).abs() < 1e-3);
+        // The Mitchell cubic integrates to one over its natural radius of 2 for any b and c
+        for (b, c) in [(1.0 / 3.0, 1.0 / 3.0), (0.0, 0.5), (1.0, 0.0)] {
+            let filter = Filter::Mitchell { radius: 2.0, b, c };
+            assert!(
+                (integral(filter) - 1.0).abs() < 1e-4,
+                "b = {}, c = {}",
+                b,
+                c
+            );
+            let half = filter.with_radius(1.0);
+            assert!((integral(half) - 0.25).abs() < 1e-4, "b = {}, c = {}", b, c);
+        }
+        // Per axis the shifted Gaussian covers sqrt(2 pi) sigma erf(r / (sigma sqrt 2)) less a
+        // strip of height g(r); for r = 3 sigma the error function is P(|Z| < 3)
+        let per_axis = (2.0 * PI).sqrt() * 0.5 * 0.997_300_203_936_739_8 - 3.0 * (-4.5f64).exp();
+        assert!((integral(Filter::GAUSSIAN) - per_axis * per_axis).abs() < 1e-4);
+    }
+
+    #[test]
+    fn weights_vanish_outside_the_radius() {
+        for filter in [
+            Filter::BOX,
+            Filter::TENT,
+            Filter::GAUSSIAN,
+            Filter::MITCHELL,
+            Filter::LANCZOS,
+        ] {
+            let radius = filter.radius();
+            assert!(filter.evaluate(0.0, 0.0) > 0.0);
+            assert_eq!(filter.evaluate(radius, 0.0), 0.0);
+            assert_eq!(filter.evaluate(0.0, -radius - 0.1), 0.0);
+            assert_eq!(filter.evaluate(0.3, 0.7), filter.evaluate(-0.3, -0.7));
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use crate::color::Color;
+
+// Owned image of linear colours, stored row by row from the top left pixel
+#[derive(Debug, Clone)]
+pub struct FrameBuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<Color>,
+}
+
+impl FrameBuffer {
+    pub fn new(width: u32, height: u32) -> FrameBuffer {
+        FrameBuffer {
+            width,
+            height,
+            pixels: vec![Color::default(); width as usize * height as usize],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[Color] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [Color] {
+        &mut self.pixels
+    }
+
+    pub fn get_pixel(&self, i: u32, j: u32) -> Color {
+        self.pixels[self.index(i, j)]
+    }
+
+    pub fn set_pixel(&mut self, i: u32, j: u32, color: Color) {
+        let index = self.index(i, j);
+        self.pixels[index] = color;
+    }
+
+    // Split the image into disjoint tiles of at most tile_size x tile_size pixels, in row major
+    // order. Each tile borrows its own pixels, so tiles can be filled from different threads.
+    pub fn tiles_mut(&mut self, tile_size: u32) -> Vec<Tile<'_>> {
+        let tile_size = tile_size.max(1);
+        let tiles_x = self.width.div_ceil(tile_size);
+        let mut tiles: Vec<Tile> = tile_rects(self.width, self.height, tile_size)
+            .into_iter()
+            .map(|(x, y, width, height)| Tile {
+                x,
+                y,
+                width,
+                height,
+                rows: vec![],
+            })
+            .collect();
+
+        if self.width == 0 {
+            return tiles;
+        }
+        for (j, mut row) in self.pixels.chunks_mut(self.width as usize).enumerate() {
+            let first_tile = (j as u32 / tile_size * tiles_x) as usize;
+            for tile in &mut tiles[first_tile..first_tile + tiles_x as usize] {
+                let (segment, rest) = row.split_at_mut(tile.width as usize);
+                tile.rows.push(segment);
+                row = rest;
+            }
+        }
+        tiles
+    }
+
+    fn index(&self, i: u32, j: u32) -> usize {
+        assert!(
+            i < self.width && j < self.height,
+            "pixel ({}, {}) out of bounds",
+            i,
+            j
+        );
+        j as usize * self.width as usize + i as usize
+    }
+}
+
+// Rectangular window into a FrameBuffer, addressed relative to its top left corner
+#[derive(Debug)]
+pub struct Tile<'a> {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    rows: Vec<&'a mut [Color]>,
+}
+
+impl Tile<'_> {
+    // Position of the top left pixel in the whole image
+    pub fn x(&self) -> u32 {
+        self.x
+    }
+
+    pub fn y(&self) -> u32 {
+        self.y
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn get_pixel(&self, i: u32, j: u32) -> Color {
+        self.rows[j as usize][i as usize]
+    }
+
+    pub fn set_pixel(&mut self, i: u32, j: u32, color: Color) {
+        self.rows[j as usize][i as usize] = color;
+    }
+}
+
+// x, y, width and height of the tiles of at most tile_size x tile_size pixels covering a
+// width x height image, in row major order
+pub(crate) fn tile_rects(width: u32, height: u32, tile_size: u32) -> Vec<(u32, u32, u32, u32)> {
+    let tile_size = tile_size.max(1);
+    let tiles_x = width.div_ceil(tile_size);
+    let tiles_y = height.div_ceil(tile_size);
+    (0..tiles_y)
+        .flat_map(|ty| (0..tiles_x).map(move |tx| (tx * tile_size, ty * tile_size)))
+        .map(|(x, y)| (x, y, tile_size.min(width - x), tile_size.min(height - y)))
+        .collect()
+}
@@ -1,22 +1,45 @@
+use std::sync::Arc;
+
 use crate::{
+    aabb::Aabb,
     interval::Interval,
-    material::Material,
+    material::{DefaultMaterial, Material},
     ray::Ray,
+    utils::{random_f64, Rng},
     vector::{Point3, Vec3},
 };
 
-#[derive(Default, Clone)]
-pub struct HitRecord {
+// Borrows the material from the object that was hit, so recording a hit never allocates
+#[derive(Clone, Copy)]
+pub struct HitRecord<'a> {
     pub t: f64,
     pub p: Point3,
     pub normal: Vec3,
     pub front_face: bool,
-    pub material: Box<dyn Material>,
+    // Surface coordinates of the hit; barycentric weights of the second and third vertex for
+    // triangles without texture coordinates
+    pub u: f64,
+    pub v: f64,
+    pub material: &'a dyn Material,
+}
+
+impl Default for HitRecord<'_> {
+    fn default() -> Self {
+        HitRecord {
+            t: 0.0,
+            p: Point3::default(),
+            normal: Vec3::default(),
+            front_face: false,
+            u: 0.0,
+            v: 0.0,
+            material: &DefaultMaterial {},
+        }
+    }
 }
 
-impl HitRecord {
+impl HitRecord<'_> {
     pub fn set_face_normal(&mut self, r: &Ray, outward_normal: &Vec3) {
-        self.front_face = r.direction().dot(&outward_normal) < 0.0;
+        self.front_face = r.direction().dot(outward_normal) < 0.0;
         self.normal = if self.front_face {
             *outward_normal
         } else {
@@ -26,36 +49,57 @@ impl HitRecord {
 }
 
 pub trait Hittable: Send + Sync {
-    fn hit(&self, ray: &Ray, ray_t: Interval, hit_record: &mut HitRecord) -> bool;
-    fn clone_box(&self) -> Box<dyn Hittable>;
+    fn hit<'a>(&'a self, ray: &Ray, ray_t: Interval, hit_record: &mut HitRecord<'a>) -> bool;
+    fn bounding_box(&self) -> Aabb;
+    // Solid angle density of `random` picking `direction` from `origin`. Only shapes that can be
+    // sampled as lights implement these two.
+    fn pdf_value(&self, _origin: &Point3, _direction: &Vec3) -> f64 {
+        0.0
+    }
+    // Direction from `origin` towards a random point on the shape
+    fn random(&self, _origin: &Point3, _rng: &mut Rng) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
 }
 
 #[derive(Default, Clone)]
 pub struct HittableList {
-    list: Vec<Box<dyn Hittable>>,
-}
-
-impl Clone for Box<dyn Hittable> {
-    fn clone(&self) -> Box<dyn Hittable> {
-        self.clone_box()
-    }
+    list: Vec<Arc<dyn Hittable>>,
+    bbox: Aabb,
 }
 
 impl HittableList {
     pub fn new() -> HittableList {
-        HittableList { list: vec![] }
+        HittableList {
+            list: vec![],
+            bbox: Aabb::EMPTY,
+        }
     }
 
-    pub fn add(&mut self, object: Box<dyn Hittable>) {
+    pub fn add(&mut self, object: Arc<dyn Hittable>) {
+        self.bbox = Aabb::enclosing(&self.bbox, &object.bounding_box());
         self.list.push(object);
     }
 
     pub fn clear(&mut self) {
         self.list.clear();
+        self.bbox = Aabb::EMPTY;
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn into_objects(self) -> Vec<Arc<dyn Hittable>> {
+        self.list
     }
 }
 impl Hittable for HittableList {
-    fn hit(&self, ray: &Ray, ray_t: Interval, hit_record: &mut HitRecord) -> bool {
+    fn hit<'a>(&'a self, ray: &Ray, ray_t: Interval, hit_record: &mut HitRecord<'a>) -> bool {
         let mut temp_rec = HitRecord::default();
         let mut hit_anything = false;
         let mut closest_so_far = ray_t.max;
@@ -64,14 +108,28 @@ impl Hittable for HittableList {
             if object.hit(ray, Interval::new(ray_t.min, closest_so_far), &mut temp_rec) {
                 hit_anything = true;
                 closest_so_far = temp_rec.t;
-                *hit_record = temp_rec.clone();
+                *hit_record = temp_rec;
             }
         }
         hit_anything
     }
-    fn clone_box(&self) -> Box<dyn Hittable> {
-        Box::new(HittableList {
-            list: self.list.iter().map(|x| x.clone_box()).collect(),
-        })
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+    // Lights are picked uniformly, so the density is the average over all of them
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        if self.list.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = self
+            .list
+            .iter()
+            .map(|object| object.pdf_value(origin, direction))
+            .sum();
+        sum / self.list.len() as f64
+    }
+    fn random(&self, origin: &Point3, rng: &mut Rng) -> Vec3 {
+        let index = (random_f64(rng) * self.list.len() as f64) as usize;
+        self.list[index.min(self.list.len() - 1)].random(origin, rng)
     }
 }
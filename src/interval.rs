@@ -1,4 +1,4 @@
-use crate::constants::{INFINITY, NEG_INFINITY};
+#[derive(Debug, Clone, Copy)]
 pub struct Interval {
     pub min: f64,
     pub max: f64,
@@ -6,18 +6,32 @@ pub struct Interval {
 
 impl Default for Interval {
     fn default() -> Self {
-        Interval {
-            min: INFINITY,
-            max: NEG_INFINITY,
-        }
+        Interval::EMPTY
     }
 }
 
 impl Interval {
+    pub const EMPTY: Interval = Interval {
+        min: f64::INFINITY,
+        max: f64::NEG_INFINITY,
+    };
+    pub const UNIVERSE: Interval = Interval {
+        min: f64::NEG_INFINITY,
+        max: f64::INFINITY,
+    };
+
     pub fn new(min: f64, max: f64) -> Interval {
         Interval { min, max }
     }
 
+    // Smallest interval enclosing both a and b
+    pub fn enclosing(a: &Interval, b: &Interval) -> Interval {
+        Interval {
+            min: a.min.min(b.min),
+            max: a.max.max(b.max),
+        }
+    }
+
     pub fn contains(&self, x: f64) -> bool {
         self.min <= x && x <= self.max
     }
@@ -30,6 +44,11 @@ impl Interval {
         self.max - self.min
     }
 
+    pub fn expand(&self, delta: f64) -> Interval {
+        let padding = delta / 2.0;
+        Interval::new(self.min - padding, self.max + padding)
+    }
+
     pub fn clamp(&self, x: f64) -> f64 {
         // Its hilarious how smart this is but also how unreadable it is
         x.max(self.min).min(self.max)
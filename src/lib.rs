@@ -1,10 +1,31 @@
+pub mod aabb;
+pub mod background;
+pub mod bvh;
 pub mod camera;
 pub mod color;
 pub mod constants;
+pub mod film;
+pub mod filter;
+pub mod framebuffer;
 pub mod hittable;
 pub mod interval;
 pub mod material;
+pub mod matrix;
+pub mod mesh;
+pub mod microfacet;
+pub mod noise;
+pub mod obj;
+pub mod onb;
+pub mod output;
+pub mod parallel;
+pub mod quad;
 pub mod ray;
+pub mod sampler;
+pub mod scene;
+pub mod spectrum;
 pub mod sphere;
+pub mod texture;
+pub mod transform;
+pub mod triangle;
 pub mod utils;
 pub mod vector;
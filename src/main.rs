@@ -1,17 +1,334 @@
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+
+use clap::{Parser, ValueEnum};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use raytracer::background::Background;
+use raytracer::bvh::BvhNode;
 use raytracer::camera::Camera;
 use raytracer::color::Color;
+use raytracer::filter::Filter;
 use raytracer::hittable::HittableList;
-use raytracer::material::{Dielectric, Lambertian, Metal};
+use raytracer::material::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+use raytracer::output::{self, ImageFormat};
+use raytracer::quad::{make_box, Quad};
+use raytracer::sampler::SamplerKind;
+use raytracer::scene::{load_scene, Scene};
 use raytracer::sphere::Sphere;
-use raytracer::utils::{random_f64, random_f64_in_range};
+use raytracer::transform::{RotateY, Translate};
 use raytracer::vector::{Point3, Vec3};
 
+#[derive(Parser)]
+#[command(about = "Render a scene file or one of the built-in scenes")]
+struct Args {
+    /// TOML scene description to render
+    #[arg(short, long, conflicts_with = "builtin")]
+    scene: Option<PathBuf>,
+
+    /// Built-in scene to render when no scene file is given
+    #[arg(short, long, value_enum, default_value_t = Builtin::RandomSpheres)]
+    builtin: Builtin,
+
+    /// Output image, the format is picked from the extension. Writes ASCII PPM to stdout when
+    /// omitted
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Output format, overriding the file extension
+    #[arg(short, long, value_enum, requires = "output")]
+    format: Option<Format>,
+
+    /// Image width in pixels
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    width: Option<u32>,
+
+    /// Width divided by height
+    #[arg(long, value_parser = positive)]
+    aspect_ratio: Option<f64>,
+
+    /// Samples per pixel
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    samples: Option<u32>,
+
+    /// How sample points are spread over the pixel, the lens and each bounce
+    #[arg(long, value_enum)]
+    sampler: Option<SamplerArg>,
+
+    /// Reconstruction filter weighting the samples around each pixel
+    #[arg(long, value_enum)]
+    filter: Option<FilterArg>,
+
+    /// Radius of the reconstruction filter in pixels, defaults to the filter's usual radius
+    #[arg(long, value_parser = positive)]
+    filter_radius: Option<f64>,
+
+    /// Trace one wavelength per path, so dispersive glass splits light into colours
+    #[arg(long)]
+    spectral: bool,
+
+    /// Stop sampling a pixel once its gamma corrected brightness is known to within this much,
+    /// 0 samples every pixel fully
+    #[arg(long)]
+    adaptive_threshold: Option<f64>,
+
+    /// Samples every pixel gets before adaptive sampling may stop
+    #[arg(long)]
+    min_samples: Option<u32>,
+
+    /// Also write a heatmap of the samples spent per pixel, the format is picked from the
+    /// extension
+    #[arg(long, conflicts_with = "progressive")]
+    heatmap: Option<PathBuf>,
+
+    /// Maximum number of ray bounces
+    #[arg(long)]
+    max_depth: Option<u32>,
+
+    /// Vertical field of view in degrees
+    #[arg(long)]
+    vfov: Option<f64>,
+
+    /// Aperture angle in degrees, 0 disables depth of field
+    #[arg(long)]
+    defocus_angle: Option<f64>,
+
+    /// Distance to the plane of perfect focus
+    #[arg(long)]
+    focus_dist: Option<f64>,
+
+    /// Number of render threads, 0 uses one per core
+    #[arg(short = 'j', long, default_value_t = 0)]
+    threads: usize,
+
+    /// Width and height in pixels of the tiles threads render at a time
+    #[arg(long)]
+    tile_size: Option<u32>,
+
+    /// Render one sample per pixel at a time, rewriting the output after every pass so the render
+    /// can be watched and stopped once it looks good
+    #[arg(long, requires = "output")]
+    progressive: bool,
+
+    /// Seed for the random built-in scene and for sampling, overriding the scene file
+    #[arg(long)]
+    seed: Option<u64>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Builtin {
+    /// The final scene of Ray Tracing in One Weekend
+    RandomSpheres,
+    /// Cornell box lit by a single ceiling light
+    CornellBox,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SamplerArg {
+    /// Uniform random numbers
+    Independent,
+    /// Jittered grid
+    Stratified,
+    /// Randomised Halton sequence
+    Halton,
+    /// Owen scrambled Sobol sequence
+    Sobol,
+}
+
+impl From<SamplerArg> for SamplerKind {
+    fn from(sampler: SamplerArg) -> Self {
+        match sampler {
+            SamplerArg::Independent => SamplerKind::Independent,
+            SamplerArg::Stratified => SamplerKind::Stratified,
+            SamplerArg::Halton => SamplerKind::Halton,
+            SamplerArg::Sobol => SamplerKind::Sobol,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum FilterArg {
+    /// Average of the samples in each pixel, radius 0.5
+    Box,
+    /// Linear falloff, radius 1
+    Tent,
+    /// Gaussian with a standard deviation of 0.5 pixels, radius 1.5
+    Gaussian,
+    /// Mitchell-Netravali with B = C = 1/3, radius 2
+    Mitchell,
+    /// Lanczos windowed sinc, radius 3
+    Lanczos,
+}
+
+impl From<FilterArg> for Filter {
+    fn from(filter: FilterArg) -> Self {
+        match filter {
+            FilterArg::Box => Filter::BOX,
+            FilterArg::Tent => Filter::TENT,
+            FilterArg::Gaussian => Filter::GAUSSIAN,
+            FilterArg::Mitchell => Filter::MITCHELL,
+            FilterArg::Lanczos => Filter::LANCZOS,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    PpmAscii,
+    Ppm,
+    Png,
+    Pfm,
+    Exr,
+}
+
+impl From<Format> for ImageFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::PpmAscii => ImageFormat::PpmAscii,
+            Format::Ppm => ImageFormat::PpmBinary,
+            Format::Png => ImageFormat::Png,
+            Format::Pfm => ImageFormat::Pfm,
+            Format::Exr => ImageFormat::Exr,
+        }
+    }
+}
+
+// Parse an argument that has to be a finite number above zero
+fn positive(arg: &str) -> Result<f64, String> {
+    let value: f64 = arg.parse().map_err(|error| format!("{}", error))?;
+    if value > 0.0 && value.is_finite() {
+        Ok(value)
+    } else {
+        Err(format!("{} is not a positive number", arg))
+    }
+}
+
 fn main() {
+    let args = Args::parse();
+    if let Err(error) = run(args) {
+        eprintln!("error: {}", error);
+        process::exit(1);
+    }
+}
+
+fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    let Scene {
+        world,
+        lights,
+        mut camera,
+    } = match &args.scene {
+        Some(path) => load_scene(path)?,
+        None => match args.builtin {
+            Builtin::RandomSpheres => random_spheres(args.seed.unwrap_or(0)),
+            Builtin::CornellBox => cornell_box(),
+        },
+    };
+
+    if let Some(width) = args.width {
+        camera.image_width = width;
+    }
+    if let Some(aspect_ratio) = args.aspect_ratio {
+        camera.aspect_ratio = aspect_ratio;
+    }
+    if let Some(samples) = args.samples {
+        camera.samples_per_pixel = samples;
+    }
+    if let Some(sampler) = args.sampler {
+        camera.sampler = sampler.into();
+    }
+    if let Some(filter) = args.filter {
+        camera.filter = filter.into();
+    }
+    if let Some(filter_radius) = args.filter_radius {
+        camera.filter = camera.filter.with_radius(filter_radius);
+    }
+    if args.spectral {
+        camera.spectral = true;
+    }
+    if let Some(adaptive_threshold) = args.adaptive_threshold {
+        camera.adaptive_threshold = adaptive_threshold;
+    }
+    if let Some(min_samples) = args.min_samples {
+        camera.min_samples_per_pixel = min_samples;
+    }
+    if let Some(max_depth) = args.max_depth {
+        camera.max_depth = max_depth;
+    }
+    if let Some(vfov) = args.vfov {
+        camera.vfov = vfov;
+    }
+    if let Some(defocus_angle) = args.defocus_angle {
+        camera.defocus_angle = defocus_angle;
+    }
+    if let Some(focus_dist) = args.focus_dist {
+        camera.focus_dist = focus_dist;
+    }
+    if let Some(seed) = args.seed {
+        camera.seed = seed;
+    }
+    camera.threads = args.threads;
+    if let Some(tile_size) = args.tile_size {
+        camera.tile_size = tile_size;
+    }
+    camera.multithreaded = args.threads != 1;
+
+    let world = BvhNode::new(world);
+    if !args.progressive {
+        let (image, heatmap) = camera.render_with_heatmap(world, &lights);
+        if let Some(path) = &args.heatmap {
+            output::save(&heatmap, path)?;
+        }
+        return match (&args.output, args.format) {
+            (Some(path), Some(format)) => Ok(output::save_as(&image, path, format.into())?),
+            (Some(path), None) => Ok(output::save(&image, path)?),
+            (None, _) => Ok(output::write_ppm(&image, &mut io::stdout().lock())?),
+        };
+    }
+
+    let path = args
+        .output
+        .as_ref()
+        .expect("--progressive requires --output");
+    let format = match args.format {
+        Some(format) => format.into(),
+        None => ImageFormat::from_path(path)
+            .ok_or_else(|| format!("unsupported image extension for {}", path.display()))?,
+    };
+    // Each pass is written next to the output and renamed over it, so the output is never seen
+    // half written
+    let mut partial = path.clone().into_os_string();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+
+    let samples = camera.samples_per_pixel;
+    let mut result = Ok(());
+    camera.render_progressive(world, &lights, |pass, image| {
+        eprint!("\rpass {}/{}", pass, samples);
+        result = output::save_as(image, &partial, format).and_then(|_| fs::rename(&partial, path));
+        if result.is_ok() {
+            ControlFlow::Continue(())
+        } else {
+            ControlFlow::Break(())
+        }
+    });
+    eprintln!();
+    Ok(result?)
+}
+
+fn random_spheres(seed: u64) -> Scene {
+    let mut rng = StdRng::seed_from_u64(seed);
+
     // World
     let mut world = HittableList::new();
 
-    let ground_material = Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
-    world.add(Box::new(Sphere::new(
+    let ground_material = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    world.add(Arc::new(Sphere::new(
         Point3::new(0.0, -1000.0, 0.0),
         1000.0,
         ground_material,
@@ -19,101 +336,68 @@ fn main() {
 
     for a in -11..11 {
         for b in -11..11 {
-            let choose_mat = random_f64();
+            let choose_mat: f64 = rng.gen();
             let center = Point3::new(
-                a as f64 + 0.9 * random_f64(),
+                a as f64 + 0.9 * rng.gen::<f64>(),
                 0.2,
-                b as f64 + 0.9 * random_f64(),
+                b as f64 + 0.9 * rng.gen::<f64>(),
             );
             if (center - Point3::new(4.0, 0.2, 0.0)).length() > 0.9 {
                 if choose_mat < 0.8 {
                     // diffuse
-                    let albedo = Color::new(random_f64(), random_f64(), random_f64());
+                    let albedo = Color::new(rng.gen(), rng.gen(), rng.gen());
                     let albedo = albedo * albedo;
-                    world.add(Box::new(Sphere::new(
+                    world.add(Arc::new(Sphere::new(
                         center,
                         0.2,
-                        Box::new(Lambertian::new(albedo)),
+                        Arc::new(Lambertian::new(albedo)),
                     )));
                 } else if choose_mat < 0.95 {
                     // metal
                     let albedo = Color::new(
-                        random_f64_in_range(0.5, 1.0),
-                        random_f64_in_range(0.5, 1.0),
-                        random_f64_in_range(0.5, 1.0),
+                        rng.gen_range(0.5..1.0),
+                        rng.gen_range(0.5..1.0),
+                        rng.gen_range(0.5..1.0),
                     );
-                    let fuzz = random_f64_in_range(0.0, 0.5);
-                    world.add(Box::new(Sphere::new(
+                    let fuzz = rng.gen_range(0.0..0.5);
+                    world.add(Arc::new(Sphere::new(
                         center,
                         0.2,
-                        Box::new(Metal::new(albedo, fuzz)),
+                        Arc::new(Metal::new(albedo, fuzz)),
                     )));
                 } else {
                     // glass
-                    world.add(Box::new(Sphere::new(
+                    world.add(Arc::new(Sphere::new(
                         center,
                         0.2,
-                        Box::new(Dielectric::new(1.5)),
+                        Arc::new(Dielectric::new(1.5)),
                     )));
                 }
             }
         }
     }
 
-    let material_1 = Box::new(Dielectric::new(1.5));
-    world.add(Box::new(Sphere::new(
+    let material_1 = Arc::new(Dielectric::new(1.5));
+    world.add(Arc::new(Sphere::new(
         Point3::new(0.0, 1.0, 0.0),
         1.0,
         material_1,
     )));
 
-    let material_2 = Box::new(Lambertian::new(Color::new(0.4, 0.2, 0.1)));
-    world.add(Box::new(Sphere::new(
+    let material_2 = Arc::new(Lambertian::new(Color::new(0.4, 0.2, 0.1)));
+    world.add(Arc::new(Sphere::new(
         Point3::new(-4.0, 1.0, 0.0),
         1.0,
         material_2,
     )));
 
-    let material_3 = Box::new(Metal::new(Color::new(0.7, 0.6, 0.5), 0.0));
-    world.add(Box::new(Sphere::new(
+    let material_3 = Arc::new(Metal::new(Color::new(0.7, 0.6, 0.5), 0.0));
+    world.add(Arc::new(Sphere::new(
         Point3::new(4.0, 1.0, 0.0),
         1.0,
         material_3,
     )));
 
-    //let material_ground = Box::new(Lambertian::new(Color::new(0.8, 0.8, 0.0)));
-    //let material_center = Box::new(Lambertian::new(Color::new(0.1, 0.2, 0.5)));
-    //let material_left = Box::new(Dielectric::new(1.5));
-    //let material_bubble = Box::new(Dielectric::new(1.0 / 1.5));
-    //let material_right = Box::new(Metal::new(Color::new(0.8, 0.6, 0.2), 0.4));
-    //
-    //world.add(Box::new(Sphere::new(
-    //    Point3::new(0.0, -100.5, -1.0),
-    //    100.0,
-    //    material_ground,
-    //)));
-    //world.add(Box::new(Sphere::new(
-    //    Point3::new(0.0, 0.0, -1.2),
-    //    0.5,
-    //    material_center,
-    //)));
-    //world.add(Box::new(Sphere::new(
-    //    Point3::new(-1.0, 0.0, -1.0),
-    //    0.5,
-    //    material_left,
-    //)));
-    //// sphere inside a sphere to make it like a bubble
-    //world.add(Box::new(Sphere::new(
-    //    Point3::new(-1.0, 0.0, -1.0),
-    //    0.4,
-    //    material_bubble,
-    //)));
-    //world.add(Box::new(Sphere::new(
-    //    Point3::new(1.0, 0.0, -1.0),
-    //    0.5,
-    //    material_right,
-    //)));
-    //
     let mut camera = Camera::new();
     camera.aspect_ratio = 16.0 / 9.0;
     camera.image_width = 1200;
@@ -128,6 +412,100 @@ fn main() {
     camera.defocus_angle = 0.6;
     camera.focus_dist = 10.0;
 
-    camera.multithreaded = true;
-    camera.render(world);
+    Scene {
+        world,
+        lights: HittableList::new(),
+        camera,
+    }
+}
+
+fn cornell_box() -> Scene {
+    let mut world = HittableList::new();
+
+    let red = Lambertian::new(Color::new(0.65, 0.05, 0.05));
+    let white: Arc<dyn Material> = Arc::new(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
+    let green = Lambertian::new(Color::new(0.12, 0.45, 0.15));
+    let light = DiffuseLight::new(Color::new(15.0, 15.0, 15.0));
+    let light = Arc::new(Quad::new(
+        Point3::new(343.0, 554.0, 332.0),
+        Vec3::new(-130.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -105.0),
+        Arc::new(light),
+    ));
+    let mut lights = HittableList::new();
+    lights.add(light.clone());
+
+    world.add(Arc::new(Quad::new(
+        Point3::new(555.0, 0.0, 0.0),
+        Vec3::new(0.0, 555.0, 0.0),
+        Vec3::new(0.0, 0.0, 555.0),
+        Arc::new(green),
+    )));
+    world.add(Arc::new(Quad::new(
+        Point3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 555.0, 0.0),
+        Vec3::new(0.0, 0.0, 555.0),
+        Arc::new(red),
+    )));
+    world.add(light);
+    world.add(Arc::new(Quad::new(
+        Point3::new(0.0, 0.0, 0.0),
+        Vec3::new(555.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 555.0),
+        white.clone(),
+    )));
+    world.add(Arc::new(Quad::new(
+        Point3::new(555.0, 555.0, 555.0),
+        Vec3::new(-555.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -555.0),
+        white.clone(),
+    )));
+    world.add(Arc::new(Quad::new(
+        Point3::new(0.0, 0.0, 555.0),
+        Vec3::new(555.0, 0.0, 0.0),
+        Vec3::new(0.0, 555.0, 0.0),
+        white.clone(),
+    )));
+
+    let box_1 = make_box(
+        &Point3::new(0.0, 0.0, 0.0),
+        &Point3::new(165.0, 330.0, 165.0),
+        white.clone(),
+    );
+    let box_1 = RotateY::new(Arc::new(box_1), 15.0);
+    world.add(Arc::new(Translate::new(
+        Arc::new(box_1),
+        Vec3::new(265.0, 0.0, 295.0),
+    )));
+
+    let box_2 = make_box(
+        &Point3::new(0.0, 0.0, 0.0),
+        &Point3::new(165.0, 165.0, 165.0),
+        white.clone(),
+    );
+    let box_2 = RotateY::new(Arc::new(box_2), -18.0);
+    world.add(Arc::new(Translate::new(
+        Arc::new(box_2),
+        Vec3::new(130.0, 0.0, 65.0),
+    )));
+
+    let mut camera = Camera::new();
+    camera.aspect_ratio = 1.0;
+    camera.image_width = 600;
+    camera.samples_per_pixel = 200;
+    camera.max_depth = 50;
+    camera.background = Background::BLACK;
+
+    camera.vfov = 40.0;
+    camera.look_from = Point3::new(278.0, 278.0, -800.0);
+    camera.look_at = Point3::new(278.0, 278.0, 0.0);
+    camera.vup = Vec3::new(0.0, 1.0, 0.0);
+
+    camera.defocus_angle = 0.0;
+
+    Scene {
+        world,
+        lights,
+        camera,
+    }
 }
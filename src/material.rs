@@ -1,168 +1,924 @@
+use std::sync::Arc;
+
 use crate::color::Color;
+use crate::constants::PI;
 use crate::hittable::HitRecord;
+use crate::microfacet::{
+    fresnel_conductor, fresnel_dielectric, reflect, refract, schlick, TrowbridgeReitz,
+};
+use crate::onb::Onb;
 use crate::ray::Ray;
-use crate::utils::random_f64;
-use crate::vector::Vec3;
+use crate::sampler::Sampler;
+use crate::spectrum::Dispersion;
+use crate::texture::{SolidColor, Texture};
+use crate::vector::{Point3, Vec3};
 
 pub struct DefaultMaterial {}
 
+// Direction picked by Material::sample for the ray leaving a hit point
+#[derive(Debug, Clone, Copy)]
+pub struct ScatterSample {
+    pub direction: Vec3,
+    // BSDF times the cosine term divided by the pdf, the factor the path's throughput picks up
+    pub weight: Color,
+    // Solid angle density of picking `direction`. Zero means the direction came from a specular
+    // bounce, which `eval` and `pdf` can't reproduce, so it can't be combined with light sampling.
+    pub pdf: f64,
+}
+
+// Directions are in world space. `ray_in` is the ray arriving at the hit point, `direction` the
+// one leaving it, towards the light when it is traced backwards from the camera.
 pub trait Material: Send + Sync {
-    fn scatter(
+    // Pick the direction the ray leaves in, None when it is absorbed
+    fn sample(
         &self,
         ray_in: &Ray,
         hit_record: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
-    ) -> bool;
-    fn clone_box(&self) -> Box<dyn Material>;
-}
-
-impl Clone for Box<dyn Material> {
-    fn clone(&self) -> Box<dyn Material> {
-        self.clone_box()
+        sampler: &mut Sampler,
+    ) -> Option<ScatterSample>;
+    // BSDF for light leaving along `direction`, without the cosine term. Black for materials
+    // that only scatter specularly.
+    fn eval(&self, _ray_in: &Ray, _hit_record: &HitRecord, _direction: &Vec3) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+    // Solid angle density with which `sample` would pick `direction`
+    fn pdf(&self, _ray_in: &Ray, _hit_record: &HitRecord, _direction: &Vec3) -> f64 {
+        0.0
+    }
+    // Light given off by the surface itself, black for everything except lights
+    fn emitted(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
+        Color::new(0.0, 0.0, 0.0)
     }
 }
 
 impl Material for DefaultMaterial {
-    fn scatter(
+    fn sample(
         &self,
         _ray_in: &Ray,
         _hit_record: &HitRecord,
-        _attenuation: &mut Color,
-        _scattered: &mut Ray,
-    ) -> bool {
-        false
-    }
-    fn clone_box(&self) -> Box<dyn Material> {
-        Box::new(DefaultMaterial {})
-    }
-}
-
-impl Default for Box<dyn Material> {
-    fn default() -> Box<dyn Material> {
-        Box::new(DefaultMaterial {})
+        _sampler: &mut Sampler,
+    ) -> Option<ScatterSample> {
+        None
     }
 }
 
+#[derive(Clone)]
 pub struct Lambertian {
-    albedo: Color,
+    texture: Arc<dyn Texture>,
 }
 
 impl Lambertian {
     pub fn new(albedo: Color) -> Lambertian {
-        Lambertian { albedo }
+        Lambertian::from_texture(Arc::new(SolidColor::new(albedo)))
+    }
+
+    pub fn from_texture(texture: Arc<dyn Texture>) -> Lambertian {
+        Lambertian { texture }
     }
 }
 impl Material for Lambertian {
-    fn scatter(
+    // Cosine weighted about the normal, so the cosine and the 1 / pi cancel against the pdf and
+    // leave the albedo
+    fn sample(
         &self,
-        _ray_in: &Ray,
+        ray_in: &Ray,
         hit_record: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
-    ) -> bool {
-        let mut scatter_direction = &hit_record.normal + &Vec3::random_unit_vector();
+        sampler: &mut Sampler,
+    ) -> Option<ScatterSample> {
+        let uvw = Onb::new(&hit_record.normal);
+        let direction = uvw.transform(&Vec3::sample_cosine_hemisphere(sampler.get_2d()));
+        Some(ScatterSample {
+            direction,
+            weight: self
+                .texture
+                .value(hit_record.u, hit_record.v, &hit_record.p),
+            pdf: self.pdf(ray_in, hit_record, &direction),
+        })
+    }
 
-        if scatter_direction.near_zero() {
-            scatter_direction = hit_record.normal;
+    fn eval(&self, _ray_in: &Ray, hit_record: &HitRecord, direction: &Vec3) -> Color {
+        if hit_record.normal.dot(direction) <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
         }
-
-        *scattered = Ray::new(hit_record.p, scatter_direction);
-        *attenuation = self.albedo;
-        true
+        self.texture
+            .value(hit_record.u, hit_record.v, &hit_record.p)
+            / PI
     }
 
-    fn clone_box(&self) -> Box<dyn Material> {
-        Box::new(Lambertian {
-            albedo: self.albedo,
-        })
+    fn pdf(&self, _ray_in: &Ray, hit_record: &HitRecord, direction: &Vec3) -> f64 {
+        let cos_theta = hit_record.normal.dot(&direction.normalize());
+        cos_theta.max(0.0) / PI
     }
 }
 
+#[derive(Clone)]
 pub struct Metal {
-    albedo: Color,
+    texture: Arc<dyn Texture>,
     fuzz: f64,
 }
 
 impl Metal {
     pub fn new(albedo: Color, fuzz: f64) -> Metal {
+        Metal::from_texture(Arc::new(SolidColor::new(albedo)), fuzz)
+    }
+
+    pub fn from_texture(texture: Arc<dyn Texture>, fuzz: f64) -> Metal {
         let fuzz = fuzz.clamp(0.0, 1.0);
-        Metal { albedo, fuzz }
+        Metal { texture, fuzz }
     }
 }
 impl Material for Metal {
-    fn scatter(
+    // The fuzz has no density worth evaluating, so even fuzzy reflections count as specular
+    fn sample(
         &self,
         ray_in: &Ray,
         hit_record: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
-    ) -> bool {
+        sampler: &mut Sampler,
+    ) -> Option<ScatterSample> {
         let reflected = &ray_in.direction().reflect(&hit_record.normal);
-        let reflected = reflected.normalize() + (Vec3::random_unit_vector() * self.fuzz);
-        *scattered = Ray::new(hit_record.p, reflected);
-        *attenuation = self.albedo;
-        true
+        let reflected =
+            reflected.normalize() + (Vec3::sample_unit_vector(sampler.get_2d()) * self.fuzz);
+        Some(ScatterSample {
+            direction: reflected,
+            weight: self
+                .texture
+                .value(hit_record.u, hit_record.v, &hit_record.p),
+            pdf: 0.0,
+        })
+    }
+}
+
+// Named metals for Conductor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConductorPreset {
+    Gold,
+    Copper,
+    Aluminium,
+    Silver,
+}
+
+impl ConductorPreset {
+    // Measured complex index of refraction, eta and k, at red, green and blue wavelengths
+    pub fn ior(self) -> (Color, Color) {
+        match self {
+            ConductorPreset::Gold => (
+                Color::new(0.143, 0.374, 1.442),
+                Color::new(3.983, 2.386, 1.603),
+            ),
+            ConductorPreset::Copper => (
+                Color::new(0.200, 0.924, 1.102),
+                Color::new(3.912, 2.452, 2.142),
+            ),
+            ConductorPreset::Aluminium => (
+                Color::new(1.657, 0.880, 0.521),
+                Color::new(9.224, 6.270, 4.837),
+            ),
+            ConductorPreset::Silver => (
+                Color::new(0.155, 0.117, 0.138),
+                Color::new(4.828, 3.122, 2.147),
+            ),
+        }
+    }
+}
+
+// Metal made of tiny perfect mirrors spread by a GGX distribution, its colour coming from the
+// Fresnel reflectance of its complex index of refraction. Unlike Metal it keeps energy, apart
+// from light that would need more than one bounce between microfacets to get out.
+#[derive(Clone)]
+pub struct Conductor {
+    eta: Color,
+    k: Color,
+    distribution: TrowbridgeReitz,
+}
+
+impl Conductor {
+    pub fn new(eta: Color, k: Color, roughness: f64) -> Conductor {
+        Conductor {
+            eta,
+            k,
+            distribution: TrowbridgeReitz::new(roughness),
+        }
+    }
+
+    pub fn from_preset(preset: ConductorPreset, roughness: f64) -> Conductor {
+        let (eta, k) = preset.ior();
+        Conductor::new(eta, k, roughness)
+    }
+
+    fn fresnel(&self, cos_theta: f64) -> Color {
+        Color::new(
+            fresnel_conductor(cos_theta, self.eta.x(), self.k.x()),
+            fresnel_conductor(cos_theta, self.eta.y(), self.k.y()),
+            fresnel_conductor(cos_theta, self.eta.z(), self.k.z()),
+        )
+    }
+
+    // Outgoing and incoming directions in the shading frame, None below the surface
+    fn local_directions(
+        &self,
+        ray_in: &Ray,
+        hit_record: &HitRecord,
+        direction: &Vec3,
+    ) -> Option<(Vec3, Vec3)> {
+        let uvw = Onb::new(&hit_record.normal);
+        let wo = uvw.to_local(&(ray_in.direction().normalize() * -1.0));
+        let wi = uvw.to_local(&direction.normalize());
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return None;
+        }
+        Some((wo, wi))
     }
+}
+
+impl Material for Conductor {
+    fn sample(
+        &self,
+        ray_in: &Ray,
+        hit_record: &HitRecord,
+        sampler: &mut Sampler,
+    ) -> Option<ScatterSample> {
+        let uvw = Onb::new(&hit_record.normal);
+        let wo = uvw.to_local(&(ray_in.direction().normalize() * -1.0));
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        if self.distribution.effectively_smooth() {
+            let wi = Vec3::new(-wo.x(), -wo.y(), wo.z());
+            return Some(ScatterSample {
+                direction: uvw.transform(&wi),
+                weight: self.fresnel(wo.z()),
+                pdf: 0.0,
+            });
+        }
 
-    fn clone_box(&self) -> Box<dyn Material> {
-        Box::new(Metal {
-            albedo: self.albedo,
-            fuzz: self.fuzz,
+        let wm = self.distribution.sample_wm(&wo, sampler.get_2d());
+        let wi = reflect(&wo, &wm);
+        if wi.z() <= 0.0 {
+            return None;
+        }
+        // D and the cosines cancel against the density of visible normals
+        let cos_theta_m = wo.dot(&wm);
+        let weight =
+            self.fresnel(cos_theta_m) * (self.distribution.g(&wo, &wi) / self.distribution.g1(&wo));
+        Some(ScatterSample {
+            direction: uvw.transform(&wi),
+            weight,
+            pdf: self.distribution.pdf(&wo, &wm) / (4.0 * cos_theta_m.abs()),
         })
     }
+
+    fn eval(&self, ray_in: &Ray, hit_record: &HitRecord, direction: &Vec3) -> Color {
+        let black = Color::new(0.0, 0.0, 0.0);
+        if self.distribution.effectively_smooth() {
+            return black;
+        }
+        let Some((wo, wi)) = self.local_directions(ray_in, hit_record, direction) else {
+            return black;
+        };
+        let wm = wo + wi;
+        if wm.near_zero() {
+            return black;
+        }
+        let wm = wm.normalize();
+        self.fresnel(wo.dot(&wm))
+            * (self.distribution.d(&wm) * self.distribution.g(&wo, &wi) / (4.0 * wo.z() * wi.z()))
+    }
+
+    fn pdf(&self, ray_in: &Ray, hit_record: &HitRecord, direction: &Vec3) -> f64 {
+        if self.distribution.effectively_smooth() {
+            return 0.0;
+        }
+        let Some((wo, wi)) = self.local_directions(ray_in, hit_record, direction) else {
+            return 0.0;
+        };
+        let wm = wo + wi;
+        if wm.near_zero() {
+            return 0.0;
+        }
+        let wm = wm.normalize();
+        self.distribution.pdf(&wo, &wm) / (4.0 * wo.dot(&wm).abs())
+    }
 }
 
+// Glass and other clear materials. A rough surface spreads reflection and refraction with a GGX
+// distribution, and light travelling through the inside is absorbed by `absorption` per unit of
+// distance, tinting thick parts more deeply than thin ones. The distance is measured when a ray
+// leaves through a back face, so the object must be closed and nothing else may sit inside it.
+// With dispersion, spectral renders bend each wavelength by its own refractive index.
 pub struct Dielectric {
     ref_idx: f64,
+    dispersion: Option<Dispersion>,
+    distribution: TrowbridgeReitz,
+    absorption: Color,
 }
 
 impl Dielectric {
     pub fn new(ref_idx: f64) -> Self {
-        Dielectric { ref_idx }
+        Dielectric::rough(ref_idx, 0.0)
+    }
+
+    pub fn rough(ref_idx: f64, roughness: f64) -> Self {
+        Dielectric::with_absorption(ref_idx, roughness, Color::new(0.0, 0.0, 0.0))
+    }
+
+    pub fn with_absorption(ref_idx: f64, roughness: f64, absorption: Color) -> Self {
+        Dielectric {
+            ref_idx,
+            dispersion: None,
+            distribution: TrowbridgeReitz::new(roughness),
+            absorption,
+        }
+    }
+
+    // RGB renders use the refractive index at the d line
+    pub fn dispersive(dispersion: Dispersion, roughness: f64, absorption: Color) -> Self {
+        Dielectric {
+            ref_idx: dispersion.ior(Dispersion::D_LINE),
+            dispersion: Some(dispersion),
+            distribution: TrowbridgeReitz::new(roughness),
+            absorption,
+        }
     }
 
     pub fn reflectance(&self, cos_theta: f64, ri: f64) -> f64 {
-        // Schlick's approximation
-        let r0 = (1.0 - ri) / (1.0 + ri);
-        let r0 = r0 * r0;
-        r0 + (1.0 - r0) * (1.0 - cos_theta).powf(5.0)
+        schlick(cos_theta, ri)
+    }
+
+    // Ratio of the refractive indices on the incoming side to the far side
+    fn relative_index(&self, ray_in: &Ray, hit_record: &HitRecord) -> f64 {
+        let ref_idx = match (self.dispersion, ray_in.wavelength()) {
+            (Some(dispersion), Some(wavelength)) => dispersion.ior(wavelength),
+            _ => self.ref_idx,
+        };
+        if hit_record.front_face {
+            1.0 / ref_idx
+        } else {
+            ref_idx
+        }
+    }
+
+    // Beer-Lambert falloff along the ray that just crossed the inside to reach a back face
+    fn transmittance(&self, ray_in: &Ray, hit_record: &HitRecord) -> Color {
+        if hit_record.front_face {
+            return Color::new(1.0, 1.0, 1.0);
+        }
+        let distance = hit_record.t * ray_in.direction().length();
+        Color::new(
+            (-self.absorption.x() * distance).exp(),
+            (-self.absorption.y() * distance).exp(),
+            (-self.absorption.z() * distance).exp(),
+        )
+    }
+
+    // Density of `direction` and the BSDF, in that order, for a rough surface
+    fn evaluate(&self, ray_in: &Ray, hit_record: &HitRecord, direction: &Vec3) -> (f64, Color) {
+        let none = (0.0, Color::new(0.0, 0.0, 0.0));
+        if self.distribution.effectively_smooth() {
+            return none;
+        }
+        let ri = self.relative_index(ray_in, hit_record);
+        let uvw = Onb::new(&hit_record.normal);
+        let wo = uvw.to_local(&(ray_in.direction().normalize() * -1.0));
+        let wi = uvw.to_local(&direction.normalize());
+        if wo.z() <= 0.0 {
+            return none;
+        }
+        let Some((wm, pdf, f)) = self.distribution.dielectric_lobe(&wo, &wi, ri) else {
+            return none;
+        };
+
+        let fresnel = fresnel_dielectric(wo.dot(&wm), ri);
+        let chance = if wi.z() > 0.0 { fresnel } else { 1.0 - fresnel };
+        (
+            pdf * chance,
+            self.transmittance(ray_in, hit_record) * (f * chance),
+        )
     }
 }
+
 impl Material for Dielectric {
-    fn scatter(
+    fn sample(
         &self,
         ray_in: &Ray,
         hit_record: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
-    ) -> bool {
-        *attenuation = Color::new(1.0, 1.0, 1.0);
-        let ri = if hit_record.front_face {
-            1.0 / self.ref_idx
+        sampler: &mut Sampler,
+    ) -> Option<ScatterSample> {
+        let ri = self.relative_index(ray_in, hit_record);
+        let transmittance = self.transmittance(ray_in, hit_record);
+
+        if self.distribution.effectively_smooth() {
+            let unit_direction = ray_in.direction().normalize();
+
+            let cos_theta = (unit_direction * -1.0).dot(&hit_record.normal).min(1.0);
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+
+            let cannot_refract = ri * sin_theta > 1.0;
+            let direction = if cannot_refract || self.reflectance(cos_theta, ri) > sampler.get_1d()
+            {
+                unit_direction.reflect(&hit_record.normal)
+            } else {
+                unit_direction.refract(&hit_record.normal, ri)
+            };
+
+            return Some(ScatterSample {
+                direction,
+                weight: transmittance,
+                pdf: 0.0,
+            });
+        }
+
+        let uvw = Onb::new(&hit_record.normal);
+        let wo = uvw.to_local(&(ray_in.direction().normalize() * -1.0));
+        if wo.z() <= 0.0 {
+            return None;
+        }
+        let wm = self.distribution.sample_wm(&wo, sampler.get_2d());
+        // Reflect or refract in proportion to the Fresnel reflectance, which then cancels out
+        let wi = if fresnel_dielectric(wo.dot(&wm), ri) > sampler.get_1d() {
+            let wi = reflect(&wo, &wm);
+            if wi.z() <= 0.0 {
+                return None;
+            }
+            wi
         } else {
-            self.ref_idx
+            match refract(&wo, &wm, 1.0 / ri) {
+                Some(wi) if wi.z() < 0.0 => wi,
+                _ => return None,
+            }
         };
-        let unit_direction = ray_in.direction().normalize();
 
-        let cos_theta = (unit_direction * -1.0).dot(&hit_record.normal).min(1.0);
-        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let weight = transmittance * (self.distribution.g(&wo, &wi) / self.distribution.g1(&wo));
+        let direction = uvw.transform(&wi);
+        Some(ScatterSample {
+            direction,
+            weight,
+            pdf: self.evaluate(ray_in, hit_record, &direction).0,
+        })
+    }
+
+    fn eval(&self, ray_in: &Ray, hit_record: &HitRecord, direction: &Vec3) -> Color {
+        self.evaluate(ray_in, hit_record, direction).1
+    }
+
+    fn pdf(&self, ray_in: &Ray, hit_record: &HitRecord, direction: &Vec3) -> f64 {
+        self.evaluate(ray_in, hit_record, direction).0
+    }
+}
+
+// Disney style principled BSDF: a diffuse base with sheen, a GGX specular layer that turns the
+// surface into a metal as `metallic` goes to 1, rough glass transmission, and a thin clear coat
+// on top. Every parameter is a texture, the scalar ones reading the texture's luminance, and
+// they all run from 0 to 1. Roughness stops a little above 0, use Conductor or Dielectric for
+// perfect mirrors and clear glass.
+#[derive(Clone)]
+pub struct Principled {
+    pub base_color: Arc<dyn Texture>,
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    pub specular: Arc<dyn Texture>, // Head on reflectance of the non-metal, 0.5 gives 4%
+    pub clearcoat: Arc<dyn Texture>,
+    pub sheen: Arc<dyn Texture>, // Extra reflection at grazing angles, as cloth has
+    pub transmission: Arc<dyn Texture>,
+    pub ior: f64, // Refractive index of the transmitting part
+}
+
+// Parameters of a Principled material at one point of its surface
+struct PrincipledLobes {
+    base_color: Color,
+    roughness: f64,
+    sheen: f64,
+    clearcoat: f64,
+    ior: f64,
+    diffuse_weight: f64,
+    transmission_weight: f64,
+    specular_color: Color,
+    distribution: TrowbridgeReitz,
+    clearcoat_distribution: TrowbridgeReitz,
+}
 
-        let cannot_refract = ri * sin_theta > 1.0;
-        let direction: Vec3;
-        if cannot_refract || self.reflectance(cos_theta, ri) > random_f64() {
-            direction = unit_direction.reflect(&hit_record.normal);
+impl Principled {
+    // Plastic of the given colour, change the fields for anything else
+    pub fn new(base_color: Color) -> Principled {
+        Principled {
+            base_color: Arc::new(SolidColor::new(base_color)),
+            metallic: constant(0.0),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            clearcoat: constant(0.0),
+            sheen: constant(0.0),
+            transmission: constant(0.0),
+            ior: 1.5,
+        }
+    }
+
+    fn lobes(&self, hit_record: &HitRecord) -> PrincipledLobes {
+        let value = |texture: &Arc<dyn Texture>| {
+            texture
+                .value(hit_record.u, hit_record.v, &hit_record.p)
+                .luminance()
+                .clamp(0.0, 1.0)
+        };
+        let base_color = self
+            .base_color
+            .value(hit_record.u, hit_record.v, &hit_record.p);
+        let metallic = value(&self.metallic);
+        let roughness = value(&self.roughness).max(0.04);
+        let transmission = value(&self.transmission);
+
+        // Dielectrics reflect up to 8% head on, metals reflect their base colour
+        let dielectric_specular = 0.08 * value(&self.specular);
+        let specular_color = Color::new(1.0, 1.0, 1.0) * (dielectric_specular * (1.0 - metallic))
+            + base_color * metallic;
+
+        PrincipledLobes {
+            base_color,
+            roughness,
+            sheen: value(&self.sheen),
+            clearcoat: value(&self.clearcoat),
+            ior: self.ior,
+            diffuse_weight: (1.0 - metallic) * (1.0 - transmission),
+            transmission_weight: (1.0 - metallic) * transmission,
+            specular_color,
+            distribution: TrowbridgeReitz::new(roughness),
+            clearcoat_distribution: TrowbridgeReitz::new(0.1),
+        }
+    }
+
+    // BSDF and density of `direction`, in that order
+    fn evaluate(&self, ray_in: &Ray, hit_record: &HitRecord, direction: &Vec3) -> (Color, f64) {
+        let lobes = self.lobes(hit_record);
+        if lobes.is_inside(hit_record) {
+            let inside = lobes.inside();
+            return (
+                inside.eval(ray_in, hit_record, direction),
+                inside.pdf(ray_in, hit_record, direction),
+            );
+        }
+        let uvw = Onb::new(&hit_record.normal);
+        let wo = uvw.to_local(&(ray_in.direction().normalize() * -1.0));
+        let wi = uvw.to_local(&direction.normalize());
+        if wo.z() <= 0.0 {
+            return (Color::new(0.0, 0.0, 0.0), 0.0);
+        }
+        lobes.evaluate(&wo, &wi)
+    }
+}
+
+impl PrincipledLobes {
+    // Back faces of transmitting surfaces are seen from inside the glass. Opaque ones look the
+    // same from both sides, the hit record's normal already facing the ray.
+    fn is_inside(&self, hit_record: &HitRecord) -> bool {
+        !hit_record.front_face && self.transmission_weight > 0.0
+    }
+
+    // Only the glass is left once a ray is inside, having got there by transmission
+    fn inside(&self) -> Dielectric {
+        Dielectric::rough(self.ior, self.roughness)
+    }
+
+    // Chances of sampling the diffuse, specular, clear coat and transmission lobes
+    fn chances(&self) -> [f64; 4] {
+        let weights = [
+            self.diffuse_weight,
+            1.0,
+            0.25 * self.clearcoat,
+            self.transmission_weight,
+        ];
+        let total: f64 = weights.iter().sum();
+        weights.map(|weight| weight / total)
+    }
+
+    // BSDF and density of all the lobes together, for directions in the shading frame
+    fn evaluate(&self, wo: &Vec3, wi: &Vec3) -> (Color, f64) {
+        let chances = self.chances();
+        let mut f = Color::new(0.0, 0.0, 0.0);
+        let mut pdf = 0.0;
+        // Share of the light getting past the clear coat to the lobes under it
+        let under_coat = 1.0 - 0.25 * self.clearcoat * (0.04 + 0.96 * schlick_weight(wo.z()));
+
+        if wi.z() > 0.0 {
+            let wh = *wo + *wi;
+            if wh.near_zero() {
+                return (f, pdf);
+            }
+            let cos_d = wi.dot(&wh.normalize());
+
+            // Burley's diffuse, which darkens smooth surfaces and brightens rough ones at
+            // grazing angles, with the sheen on top
+            let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+            let diffuse = (1.0 + (fd90 - 1.0) * schlick_weight(wi.z()))
+                * (1.0 + (fd90 - 1.0) * schlick_weight(wo.z()))
+                / PI;
+            let sheen = self.sheen * schlick_weight(cos_d);
+            // Light the specular lobe reflects never reaches the layers under it, on the way in
+            // or on the way out
+            let white = Color::new(1.0, 1.0, 1.0);
+            let transmitted =
+                |cos_theta: f64| (white - self.specular_color) * (1.0 - schlick_weight(cos_theta));
+            f += (self.base_color * diffuse + Color::new(sheen, sheen, sheen))
+                * transmitted(wo.z())
+                * transmitted(wi.z())
+                * self.diffuse_weight;
+            pdf += chances[0] * wi.z() / PI;
+
+            if let Some((_, lobe_pdf, lobe_f)) = self.distribution.dielectric_lobe(wo, wi, 1.0) {
+                let fresnel = self.specular_color
+                    + (Color::new(1.0, 1.0, 1.0) - self.specular_color) * schlick_weight(cos_d);
+                f += fresnel * lobe_f;
+                pdf += chances[1] * lobe_pdf;
+            }
+            f *= under_coat;
+
+            if let Some((_, lobe_pdf, lobe_f)) =
+                self.clearcoat_distribution.dielectric_lobe(wo, wi, 1.0)
+            {
+                let fresnel = 0.04 + 0.96 * schlick_weight(cos_d);
+                let coat = 0.25 * self.clearcoat * fresnel * lobe_f;
+                f += Color::new(coat, coat, coat);
+                pdf += chances[2] * lobe_pdf;
+            }
+        } else {
+            let ri = 1.0 / self.ior;
+            if let Some((wm, lobe_pdf, lobe_f)) = self.distribution.dielectric_lobe(wo, wi, ri) {
+                let fresnel = fresnel_dielectric(wo.dot(&wm), ri);
+                f += self.base_color
+                    * (self.transmission_weight * (1.0 - fresnel) * lobe_f * under_coat);
+                pdf += chances[3] * lobe_pdf;
+            }
+        }
+        (f, pdf)
+    }
+}
+
+impl Material for Principled {
+    // Pick one lobe to sample, then weigh the direction by every lobe that could have produced it
+    fn sample(
+        &self,
+        ray_in: &Ray,
+        hit_record: &HitRecord,
+        sampler: &mut Sampler,
+    ) -> Option<ScatterSample> {
+        let lobes = self.lobes(hit_record);
+        if lobes.is_inside(hit_record) {
+            return lobes.inside().sample(ray_in, hit_record, sampler);
+        }
+
+        let uvw = Onb::new(&hit_record.normal);
+        let wo = uvw.to_local(&(ray_in.direction().normalize() * -1.0));
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        let chances = lobes.chances();
+        let u = sampler.get_1d();
+        let sample = sampler.get_2d();
+        let wi = if u < chances[0] {
+            Vec3::sample_cosine_hemisphere(sample)
+        } else if u < chances[0] + chances[1] {
+            reflect(&wo, &lobes.distribution.sample_wm(&wo, sample))
+        } else if u < chances[0] + chances[1] + chances[2] {
+            reflect(&wo, &lobes.clearcoat_distribution.sample_wm(&wo, sample))
         } else {
-            direction = unit_direction.refract(&hit_record.normal, ri);
+            let wm = lobes.distribution.sample_wm(&wo, sample);
+            refract(&wo, &wm, lobes.ior)?
+        };
+
+        // A reflection can't go below the surface, nor a refraction above it
+        let transmitted = u >= chances[0] + chances[1] + chances[2];
+        if (wi.z() < 0.0) != transmitted {
+            return None;
+        }
+
+        let (f, pdf) = lobes.evaluate(&wo, &wi);
+        if pdf <= 0.0 {
+            return None;
         }
+        Some(ScatterSample {
+            direction: uvw.transform(&wi),
+            weight: f * (wi.z().abs() / pdf),
+            pdf,
+        })
+    }
 
-        *scattered = Ray::new(hit_record.p, direction);
-        true
+    fn eval(&self, ray_in: &Ray, hit_record: &HitRecord, direction: &Vec3) -> Color {
+        self.evaluate(ray_in, hit_record, direction).0
     }
 
-    fn clone_box(&self) -> Box<dyn Material> {
-        Box::new(Dielectric {
-            ref_idx: self.ref_idx,
+    fn pdf(&self, ray_in: &Ray, hit_record: &HitRecord, direction: &Vec3) -> f64 {
+        self.evaluate(ray_in, hit_record, direction).1
+    }
+}
+
+fn constant(value: f64) -> Arc<dyn Texture> {
+    Arc::new(SolidColor::new(Color::new(value, value, value)))
+}
+
+// (1 - cos)^5, how Schlick's approximation grows towards grazing angles
+fn schlick_weight(cos_theta: f64) -> f64 {
+    (1.0 - cos_theta).clamp(0.0, 1.0).powi(5)
+}
+
+#[derive(Clone)]
+pub struct DiffuseLight {
+    texture: Arc<dyn Texture>,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Color) -> Self {
+        DiffuseLight::from_texture(Arc::new(SolidColor::new(emit)))
+    }
+
+    pub fn from_texture(texture: Arc<dyn Texture>) -> Self {
+        DiffuseLight { texture }
+    }
+}
+impl Material for DiffuseLight {
+    fn sample(
+        &self,
+        _ray_in: &Ray,
+        _hit_record: &HitRecord,
+        _sampler: &mut Sampler,
+    ) -> Option<ScatterSample> {
+        None
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: &Point3) -> Color {
+        self.texture.value(u, v, p)
+    }
+}
+
+// How much of the second material of a MixMaterial shows at a point, from 0 for only the first
+// to 1 for only the second
+#[derive(Clone)]
+pub enum MixWeight {
+    // Luminance of a texture, such as a mask of where paint or dust covers the surface
+    Texture(Arc<dyn Texture>),
+    // Schlick's reflectance of a dielectric with this refractive index, so the second material
+    // takes over at grazing angles as a coating on top of the first would
+    Fresnel(f64),
+}
+
+// Two materials on one surface. Each ray picks one of them at random by the weight, and
+// directions are weighed by both so light sampling still works.
+#[derive(Clone)]
+pub struct MixMaterial {
+    first: Arc<dyn Material>,
+    second: Arc<dyn Material>,
+    weight: MixWeight,
+}
+
+impl MixMaterial {
+    pub fn new(first: Arc<dyn Material>, second: Arc<dyn Material>, weight: f64) -> MixMaterial {
+        MixMaterial::from_texture(first, second, constant(weight))
+    }
+
+    pub fn from_texture(
+        first: Arc<dyn Material>,
+        second: Arc<dyn Material>,
+        weight: Arc<dyn Texture>,
+    ) -> MixMaterial {
+        MixMaterial {
+            first,
+            second,
+            weight: MixWeight::Texture(weight),
+        }
+    }
+
+    // `coat` over `base`, showing as much as a dielectric of refractive index `ior` reflects
+    pub fn fresnel(base: Arc<dyn Material>, coat: Arc<dyn Material>, ior: f64) -> MixMaterial {
+        MixMaterial {
+            first: base,
+            second: coat,
+            weight: MixWeight::Fresnel(ior),
+        }
+    }
+
+    fn weight(&self, ray_in: &Ray, hit_record: &HitRecord) -> f64 {
+        match &self.weight {
+            MixWeight::Texture(texture) => texture
+                .value(hit_record.u, hit_record.v, &hit_record.p)
+                .luminance()
+                .clamp(0.0, 1.0),
+            MixWeight::Fresnel(ior) => {
+                let cos_theta = ray_in.direction().normalize().dot(&hit_record.normal).abs();
+                schlick(cos_theta.min(1.0), *ior)
+            }
+        }
+    }
+}
+
+impl Material for MixMaterial {
+    fn sample(
+        &self,
+        ray_in: &Ray,
+        hit_record: &HitRecord,
+        sampler: &mut Sampler,
+    ) -> Option<ScatterSample> {
+        let weight = self.weight(ray_in, hit_record);
+        let chosen = if sampler.get_1d() < weight {
+            &self.second
+        } else {
+            &self.first
+        };
+        let sample = chosen.sample(ray_in, hit_record, sampler)?;
+        // Specular bounces are only ever picked with the chance of their own material, which is
+        // already the share of it in the mix
+        if sample.pdf == 0.0 {
+            return Some(sample);
+        }
+
+        let f = self.eval(ray_in, hit_record, &sample.direction);
+        let pdf = self.pdf(ray_in, hit_record, &sample.direction);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let cos_theta = sample.direction.normalize().dot(&hit_record.normal).abs();
+        Some(ScatterSample {
+            direction: sample.direction,
+            weight: f * (cos_theta / pdf),
+            pdf,
         })
     }
+
+    fn eval(&self, ray_in: &Ray, hit_record: &HitRecord, direction: &Vec3) -> Color {
+        let weight = self.weight(ray_in, hit_record);
+        self.first.eval(ray_in, hit_record, direction) * (1.0 - weight)
+            + self.second.eval(ray_in, hit_record, direction) * weight
+    }
+
+    fn pdf(&self, ray_in: &Ray, hit_record: &HitRecord, direction: &Vec3) -> f64 {
+        let weight = self.weight(ray_in, hit_record);
+        self.first.pdf(ray_in, hit_record, direction) * (1.0 - weight)
+            + self.second.pdf(ray_in, hit_record, direction) * weight
+    }
+
+    // Emission has no direction to take a Fresnel weight at, so it uses the head on one
+    fn emitted(&self, u: f64, v: f64, p: &Point3) -> Color {
+        let weight = match &self.weight {
+            MixWeight::Texture(texture) => texture.value(u, v, p).luminance().clamp(0.0, 1.0),
+            MixWeight::Fresnel(ior) => schlick(1.0, *ior),
+        };
+        self.first.emitted(u, v, p) * (1.0 - weight) + self.second.emitted(u, v, p) * weight
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::background::Background;
+    use crate::hittable::{Hittable, HittableList};
+    use crate::sampler::SamplerKind;
+    use crate::sphere::Sphere;
+
+    // Average light a ray sees off a floor of `material` under a spherical light, with or without
+    // sampling the light directly
+    fn floor_radiance(material: Arc<dyn Material>, sample_lights: bool) -> f64 {
+        let light: Arc<dyn Hittable> = Arc::new(Sphere::new(
+            Point3::new(0.0, 3.0, 0.0),
+            1.5,
+            Arc::new(DiffuseLight::new(Color::new(2.0, 2.0, 2.0))),
+        ));
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere::new(
+            Point3::new(0.0, -1000.0, 0.0),
+            1000.0,
+            material,
+        )));
+        world.add(light.clone());
+        let mut lights = HittableList::new();
+        if sample_lights {
+            lights.add(light);
+        }
+
+        let ray = Ray::new(Point3::new(-2.0, 2.0, 0.0), Vec3::new(2.0, -2.0, 0.3));
+        let samples = 100_000;
+        let total: f64 = (0..samples)
+            .map(|i| {
+                let mut sampler = Sampler::new(SamplerKind::Independent, 1, 7, i, 0);
+                ray.color(8, &world, &lights, &Background::BLACK, &mut sampler)
+                    .luminance()
+            })
+            .sum();
+        total / samples as f64
+    }
+
+    #[test]
+    fn mix_with_specular_matches_reference_without_light_sampling() {
+        let diffuse: Arc<dyn Material> = Arc::new(Lambertian::new(Color::new(0.6, 0.6, 0.6)));
+        let mirror: Arc<dyn Material> = Arc::new(Metal::new(Color::new(0.9, 0.9, 0.9), 0.0));
+        let materials = [
+            MixMaterial::new(diffuse.clone(), mirror.clone(), 0.5),
+            MixMaterial::fresnel(diffuse, mirror, 1.5),
+        ];
+        for material in materials {
+            let material: Arc<dyn Material> = Arc::new(material);
+            let sampled = floor_radiance(material.clone(), true);
+            let reference = floor_radiance(material, false);
+            assert!(
+                (sampled - reference).abs() < 0.02 * reference,
+                "{sampled} with light sampling, {reference} without"
+            );
+        }
+    }
 }
@@ -0,0 +1,144 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::Aabb,
+    bvh::BvhNode,
+    hittable::{HitRecord, Hittable},
+    interval::Interval,
+    material::Material,
+    ray::Ray,
+    triangle::intersect,
+    vector::{Point3, Vec3},
+};
+
+// Vertex buffers for an indexed triangle mesh. Normals and uvs are optional, but when present
+// they hold one entry per position and are indexed by the same triangle indices.
+#[derive(Debug, Default, Clone)]
+pub struct Mesh {
+    pub positions: Vec<Point3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f64, f64)>,
+    pub indices: Vec<[usize; 3]>,
+}
+
+struct MeshData {
+    mesh: Mesh,
+    material: Arc<dyn Material>,
+}
+
+// A mesh of triangles sharing one set of vertex buffers and one material. Interpolated vertex
+// normals give smooth shading when the mesh has them.
+#[derive(Clone)]
+pub struct TriangleMesh {
+    bvh: BvhNode,
+}
+
+impl TriangleMesh {
+    pub fn new(mesh: Mesh, material: Arc<dyn Material>) -> TriangleMesh {
+        let vertex_count = mesh.positions.len();
+        assert!(
+            mesh.normals.is_empty() || mesh.normals.len() == vertex_count,
+            "mesh needs one normal per vertex"
+        );
+        assert!(
+            mesh.uvs.is_empty() || mesh.uvs.len() == vertex_count,
+            "mesh needs one uv per vertex"
+        );
+        assert!(
+            mesh.indices.iter().flatten().all(|&i| i < vertex_count),
+            "mesh index out of range"
+        );
+
+        let data = Arc::new(MeshData { mesh, material });
+        let triangles = (0..data.mesh.indices.len())
+            .map(|index| {
+                Arc::new(MeshTriangle {
+                    data: Arc::clone(&data),
+                    index,
+                }) as Arc<dyn Hittable>
+            })
+            .collect();
+
+        TriangleMesh {
+            bvh: BvhNode::from_objects(triangles),
+        }
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit<'a>(&'a self, ray: &Ray, ray_t: Interval, hit_record: &mut HitRecord<'a>) -> bool {
+        self.bvh.hit(ray, ray_t, hit_record)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bvh.bounding_box()
+    }
+}
+
+// One face of a TriangleMesh, referring back into the shared buffers
+#[derive(Clone)]
+struct MeshTriangle {
+    data: Arc<MeshData>,
+    index: usize,
+}
+
+impl MeshTriangle {
+    fn vertices(&self) -> [usize; 3] {
+        self.data.mesh.indices[self.index]
+    }
+}
+
+impl Hittable for MeshTriangle {
+    fn hit<'a>(&'a self, ray: &Ray, ray_t: Interval, rec: &mut HitRecord<'a>) -> bool {
+        let mesh = &self.data.mesh;
+        let [i0, i1, i2] = self.vertices();
+        let (v0, v1, v2) = (
+            &mesh.positions[i0],
+            &mesh.positions[i1],
+            &mesh.positions[i2],
+        );
+
+        let Some((t, b1, b2)) = intersect(ray, &ray_t, v0, v1, v2) else {
+            return false;
+        };
+        let b0 = 1.0 - b1 - b2;
+
+        rec.t = t;
+        rec.p = ray.at(t);
+
+        if mesh.uvs.is_empty() {
+            rec.u = b1;
+            rec.v = b2;
+        } else {
+            let (uv0, uv1, uv2) = (mesh.uvs[i0], mesh.uvs[i1], mesh.uvs[i2]);
+            rec.u = b0 * uv0.0 + b1 * uv1.0 + b2 * uv2.0;
+            rec.v = b0 * uv0.1 + b1 * uv1.1 + b2 * uv2.1;
+        }
+
+        // The geometric normal decides which side was hit, the shading normal is then flipped to
+        // agree with it
+        let geometric_normal = (v1 - v0).cross(&(v2 - v0)).normalize();
+        rec.set_face_normal(ray, &geometric_normal);
+        if !mesh.normals.is_empty() {
+            let shading_normal =
+                (mesh.normals[i0] * b0 + mesh.normals[i1] * b1 + mesh.normals[i2] * b2).normalize();
+            rec.normal = if shading_normal.dot(&rec.normal) < 0.0 {
+                shading_normal * -1.0
+            } else {
+                shading_normal
+            };
+        }
+
+        rec.material = self.data.material.as_ref();
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let mesh = &self.data.mesh;
+        let [i0, i1, i2] = self.vertices();
+        Aabb::enclosing(
+            &Aabb::from_points(&mesh.positions[i0], &mesh.positions[i1]),
+            &Aabb::from_points(&mesh.positions[i0], &mesh.positions[i2]),
+        )
+    }
+}
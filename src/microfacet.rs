@@ -0,0 +1,181 @@
+use crate::constants::PI;
+use crate::vector::Vec3;
+
+// Trowbridge-Reitz (GGX) distribution of microfacet normals with Smith shadowing and masking.
+// Directions are in the local shading frame, with the macro surface normal along +z.
+#[derive(Debug, Clone, Copy)]
+pub struct TrowbridgeReitz {
+    alpha: f64,
+}
+
+impl TrowbridgeReitz {
+    // Roughness runs from 0 for a mirror to 1 for a very rough surface. Squaring it makes the
+    // look change evenly along the way.
+    pub fn new(roughness: f64) -> TrowbridgeReitz {
+        let roughness = roughness.clamp(0.0, 1.0);
+        TrowbridgeReitz {
+            alpha: roughness * roughness,
+        }
+    }
+
+    // Too smooth to sample without numerical trouble, treat it as a perfect mirror instead
+    pub fn effectively_smooth(&self) -> bool {
+        self.alpha < 1e-3
+    }
+
+    // Density of microfacets facing along `wm`, per unit of macro surface area
+    pub fn d(&self, wm: &Vec3) -> f64 {
+        let cos2_theta = wm.z() * wm.z();
+        if cos2_theta <= 0.0 {
+            return 0.0;
+        }
+        let tan2_theta = (1.0 - cos2_theta) / cos2_theta;
+        let alpha2 = self.alpha * self.alpha;
+        let e = 1.0 + tan2_theta / alpha2;
+        1.0 / (PI * alpha2 * cos2_theta * cos2_theta * e * e)
+    }
+
+    // Hidden microfacet area per visible area in direction `w`
+    fn lambda(&self, w: &Vec3) -> f64 {
+        let cos2_theta = w.z() * w.z();
+        if cos2_theta <= 0.0 {
+            return f64::INFINITY;
+        }
+        let tan2_theta = (1.0 - cos2_theta) / cos2_theta;
+        ((1.0 + self.alpha * self.alpha * tan2_theta).sqrt() - 1.0) / 2.0
+    }
+
+    // Fraction of the microfacets visible from `w`
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // Fraction of the microfacets visible from both `wo` and `wi`
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Density of the normals seen from `wo`, which is what `sample_wm` picks from
+    pub fn pdf(&self, wo: &Vec3, wm: &Vec3) -> f64 {
+        let cos_theta_o = wo.z().abs();
+        if cos_theta_o <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) / cos_theta_o * self.d(wm) * wo.dot(wm).abs()
+    }
+
+    // Pick a microfacet normal visible from `wo` from a point of the unit square (Heitz 2018)
+    pub fn sample_wm(&self, wo: &Vec3, sample: (f64, f64)) -> Vec3 {
+        // Stretch the view direction so the microfacets become a hemisphere
+        let wh = Vec3::new(self.alpha * wo.x(), self.alpha * wo.y(), wo.z()).normalize();
+        let wh = if wh.z() < 0.0 { wh * -1.0 } else { wh };
+
+        let t1 = if wh.z() < 0.99999 {
+            Vec3::new(0.0, 0.0, 1.0).cross(&wh).normalize()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = wh.cross(&t1);
+
+        // Squash a disk sample onto the part of the hemisphere that faces wh
+        let p = Vec3::sample_unit_disk(sample);
+        let h = (1.0 - p.x() * p.x()).max(0.0).sqrt();
+        let s = (1.0 + wh.z()) / 2.0;
+        let py = (1.0 - s) * h + s * p.y();
+        let pz = (1.0 - p.x() * p.x() - py * py).max(0.0).sqrt();
+        let nh = t1 * p.x() + t2 * py + wh * pz;
+
+        Vec3::new(self.alpha * nh.x(), self.alpha * nh.y(), nh.z().max(1e-6)).normalize()
+    }
+
+    // Microfacet normal that turns `wo` into `wi` by reflection, or by refraction into a medium
+    // 1 / ri times as dense, with the density of picking `wi` through it and the BSDF without
+    // the Fresnel term. None for pairs of directions no microfacet connects.
+    pub fn dielectric_lobe(&self, wo: &Vec3, wi: &Vec3, ri: f64) -> Option<(Vec3, f64, f64)> {
+        let reflect = wi.z() > 0.0;
+        // Refraction bends wi towards the normal by 1 / ri, the generalised half vector undoes it
+        let etap = if reflect { 1.0 } else { 1.0 / ri };
+        let wm = *wi * etap + *wo;
+        if wi.z() == 0.0 || wm.near_zero() {
+            return None;
+        }
+        let wm = wm.normalize();
+        let wm = if wm.z() < 0.0 { wm * -1.0 } else { wm };
+        // Light can't pass through the back of a microfacet
+        if wm.dot(wi) * wi.z() < 0.0 || wm.dot(wo) < 0.0 {
+            return None;
+        }
+
+        let d = self.d(&wm);
+        let g = self.g(wo, wi);
+        let visible = self.pdf(wo, &wm);
+        if reflect {
+            let pdf = visible / (4.0 * wo.dot(&wm));
+            let f = d * g / (4.0 * wi.z() * wo.z());
+            Some((wm, pdf, f))
+        } else {
+            let denom = (wi.dot(&wm) + wo.dot(&wm) / etap).powi(2);
+            let pdf = visible * wi.dot(&wm).abs() / denom;
+            let f = d * g * (wi.dot(&wm) * wo.dot(&wm) / (denom * wi.z() * wo.z())).abs();
+            Some((wm, pdf, f))
+        }
+    }
+}
+
+// Schlick's approximation of the reflectance of a dielectric, `ri` being the ratio of the
+// refractive indices on the incoming side to the far side
+pub fn schlick(cos_theta: f64, ri: f64) -> f64 {
+    let r0 = (1.0 - ri) / (1.0 + ri);
+    let r0 = r0 * r0;
+    r0 + (1.0 - r0) * (1.0 - cos_theta).powf(5.0)
+}
+
+// Schlick's approximation plus total internal reflection, which it misses
+pub fn fresnel_dielectric(cos_theta: f64, ri: f64) -> f64 {
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    if ri * sin_theta > 1.0 {
+        1.0
+    } else {
+        schlick(cos_theta, ri)
+    }
+}
+
+// Reflectance of a conductor with complex index of refraction eta + ik, for light arriving at
+// `cos_theta` to the normal from a vacuum
+pub fn fresnel_conductor(cos_theta: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = cos_theta.clamp(0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+    let eta2 = eta * eta;
+    let k2 = k * k;
+
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_theta * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+
+    0.5 * (rp + rs)
+}
+
+// Mirror direction of `wo` about the microfacet normal `wm`
+pub fn reflect(wo: &Vec3, wm: &Vec3) -> Vec3 {
+    *wm * (2.0 * wo.dot(wm)) - *wo
+}
+
+// Direction `wo` takes through the microfacet with normal `wm` into a medium `etap` times as
+// dense, None under total internal reflection
+pub fn refract(wo: &Vec3, wm: &Vec3, etap: f64) -> Option<Vec3> {
+    let cos_theta_i = wo.dot(wm);
+    let sin2_theta_i = (1.0 - cos_theta_i * cos_theta_i).max(0.0);
+    let sin2_theta_t = sin2_theta_i / (etap * etap);
+    if sin2_theta_t >= 1.0 {
+        return None;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+    Some(*wo * (-1.0 / etap) + *wm * (cos_theta_i / etap - cos_theta_t))
+}
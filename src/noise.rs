@@ -0,0 +1,171 @@
+use crate::{
+    utils::{random_f64, seeded_rng, Rng},
+    vector::{Point3, Vec3},
+};
+
+const POINT_COUNT: usize = 256;
+
+// Perlin gradient noise over a lattice of random unit vectors
+#[derive(Debug, Clone)]
+pub struct Perlin {
+    randvec: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+    // The same seed always builds the same lattice
+    pub fn new(seed: u64) -> Perlin {
+        let mut rng = seeded_rng(seed, 0);
+        let randvec = (0..POINT_COUNT)
+            .map(|_| Vec3::random_in_range(-1.0, 1.0, &mut rng).normalize())
+            .collect();
+        Perlin {
+            randvec,
+            perm_x: generate_perm(&mut rng),
+            perm_y: generate_perm(&mut rng),
+            perm_z: generate_perm(&mut rng),
+        }
+    }
+
+    // Smooth noise in roughly [-1, 1]
+    pub fn noise(&self, p: &Point3) -> f64 {
+        let u = p.x() - p.x().floor();
+        let v = p.y() - p.y().floor();
+        let w = p.z() - p.z().floor();
+
+        let i = p.x().floor() as i64;
+        let j = p.y().floor() as i64;
+        let k = p.z().floor() as i64;
+
+        let mut c = [[[Vec3::default(); 2]; 2]; 2];
+        for (di, plane) in c.iter_mut().enumerate() {
+            for (dj, row) in plane.iter_mut().enumerate() {
+                for (dk, corner) in row.iter_mut().enumerate() {
+                    *corner = self.randvec[self.perm_x[wrap(i + di as i64)]
+                        ^ self.perm_y[wrap(j + dj as i64)]
+                        ^ self.perm_z[wrap(k + dk as i64)]];
+                }
+            }
+        }
+
+        Perlin::perlin_interp(&c, u, v, w)
+    }
+
+    // Sum of `depth` octaves of noise, each at twice the frequency and half the weight
+    pub fn turb(&self, p: &Point3, depth: u32) -> f64 {
+        self.fbm(p, depth, 2.0, 0.5).abs()
+    }
+
+    // Fractional Brownian motion: `octaves` layers of noise, each `lacunarity` times the
+    // frequency and `gain` times the amplitude of the one before. Signed, unlike turb.
+    pub fn fbm(&self, p: &Point3, octaves: u32, lacunarity: f64, gain: f64) -> f64 {
+        let mut accum = 0.0;
+        let mut temp_p = *p;
+        let mut weight = 1.0;
+
+        for _ in 0..octaves {
+            accum += weight * self.noise(&temp_p);
+            weight *= gain;
+            temp_p *= lacunarity;
+        }
+
+        accum
+    }
+
+    fn perlin_interp(c: &[[[Vec3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
+        // Hermite smoothing hides the lattice
+        let uu = u * u * (3.0 - 2.0 * u);
+        let vv = v * v * (3.0 - 2.0 * v);
+        let ww = w * w * (3.0 - 2.0 * w);
+        let mut accum = 0.0;
+
+        for (i, plane) in c.iter().enumerate() {
+            for (j, row) in plane.iter().enumerate() {
+                for (k, corner) in row.iter().enumerate() {
+                    let (fi, fj, fk) = (i as f64, j as f64, k as f64);
+                    let weight_v = Vec3::new(u - fi, v - fj, w - fk);
+                    accum += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                        * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                        * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                        * corner.dot(&weight_v);
+                }
+            }
+        }
+
+        accum
+    }
+}
+
+// Cellular noise: one random feature point per unit cell, measured by the distance to the
+// nearest ones
+#[derive(Debug, Clone)]
+pub struct Worley {
+    points: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Worley {
+    // The same seed always places the same feature points
+    pub fn new(seed: u64) -> Worley {
+        let mut rng = seeded_rng(seed, 1);
+        let points = (0..POINT_COUNT).map(|_| Vec3::random(&mut rng)).collect();
+        Worley {
+            points,
+            perm_x: generate_perm(&mut rng),
+            perm_y: generate_perm(&mut rng),
+            perm_z: generate_perm(&mut rng),
+        }
+    }
+
+    // Distance to the nearest feature point, usually below 1
+    pub fn noise(&self, p: &Point3) -> f64 {
+        self.distances(p).0
+    }
+
+    // Distances to the nearest and second nearest feature points. Their difference gives the
+    // familiar cell walls.
+    pub fn distances(&self, p: &Point3) -> (f64, f64) {
+        let i = p.x().floor() as i64;
+        let j = p.y().floor() as i64;
+        let k = p.z().floor() as i64;
+
+        let mut nearest = (f64::INFINITY, f64::INFINITY);
+        // A point further than the neighbouring cells can't beat the one in this cell
+        for di in -1..=1 {
+            for dj in -1..=1 {
+                for dk in -1..=1 {
+                    let (ci, cj, ck) = (i + di, j + dj, k + dk);
+                    let offset = self.points
+                        [self.perm_x[wrap(ci)] ^ self.perm_y[wrap(cj)] ^ self.perm_z[wrap(ck)]];
+                    let feature = Point3::new(ci as f64, cj as f64, ck as f64) + offset;
+                    let distance = (feature - *p).length();
+                    if distance < nearest.0 {
+                        nearest = (distance, nearest.0);
+                    } else if distance < nearest.1 {
+                        nearest.1 = distance;
+                    }
+                }
+            }
+        }
+
+        nearest
+    }
+}
+
+fn wrap(i: i64) -> usize {
+    (i & (POINT_COUNT as i64 - 1)) as usize
+}
+
+fn generate_perm(rng: &mut Rng) -> Vec<usize> {
+    let mut p: Vec<usize> = (0..POINT_COUNT).collect();
+    // Fisher-Yates shuffle
+    for i in (1..POINT_COUNT).rev() {
+        let target = (random_f64(rng) * (i + 1) as f64) as usize;
+        p.swap(i, target.min(i));
+    }
+    p
+}
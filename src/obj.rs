@@ -0,0 +1,572 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::{
+    color::Color,
+    hittable::HittableList,
+    material::{Dielectric, DiffuseLight, Lambertian, Material, Metal},
+    mesh::{Mesh, TriangleMesh},
+    vector::{Point3, Vec3},
+};
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            ObjError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ObjError::Io { source, .. } => Some(source),
+            ObjError::Parse { .. } => None,
+        }
+    }
+}
+
+// Load a Wavefront OBJ file (and the MTL libraries it references) into one TriangleMesh per
+// material. Faces with more than three vertices are triangulated as fans, faces before any
+// `usemtl` get a grey Lambertian.
+pub fn load_obj<P: AsRef<Path>>(path: P) -> Result<HittableList, ObjError> {
+    let path = path.as_ref();
+    let mut parser = ObjParser::new(path);
+    for_each_line(path, |line_number, line| {
+        parser.line = line_number;
+        parser.parse_line(line)
+    })?;
+    Ok(parser.into_world())
+}
+
+// The subset of MTL we can map onto the existing materials
+#[derive(Debug, Clone)]
+struct MtlMaterial {
+    diffuse: Color,
+    specular: Option<Color>,
+    emission: Color,
+    shininess: f64,
+    optical_density: f64,
+    dissolve: f64,
+    illum: u32,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        MtlMaterial {
+            diffuse: Color::new(0.8, 0.8, 0.8),
+            specular: None,
+            emission: Color::new(0.0, 0.0, 0.0),
+            shininess: 0.0,
+            optical_density: 1.5,
+            dissolve: 1.0,
+            illum: 2,
+        }
+    }
+}
+
+impl MtlMaterial {
+    fn to_material(&self) -> Arc<dyn Material> {
+        if !self.emission.near_zero() {
+            Arc::new(DiffuseLight::new(self.emission))
+        } else if self.dissolve < 1.0 || matches!(self.illum, 4 | 6 | 7 | 9) {
+            // illum 4, 6, 7 and 9 are the refraction / glass models
+            Arc::new(Dielectric::new(self.optical_density))
+        } else if matches!(self.illum, 3 | 5) {
+            // Map the Phong exponent to a roughness, high exponents give sharp reflections
+            let fuzz = (2.0 / (self.shininess + 2.0)).sqrt();
+            // Without a Ks the reflection takes the surface colour, as in a plain metal
+            Arc::new(Metal::new(self.specular.unwrap_or(self.diffuse), fuzz))
+        } else {
+            Arc::new(Lambertian::new(self.diffuse))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct VertexRef {
+    position: usize,
+    uv: Option<usize>,
+    normal: Option<usize>,
+}
+
+struct FaceGroup {
+    material: Option<String>,
+    faces: Vec<[VertexRef; 3]>,
+}
+
+struct ObjParser {
+    path: PathBuf,
+    line: usize,
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<(f64, f64)>,
+    materials: HashMap<String, MtlMaterial>,
+    groups: Vec<FaceGroup>,
+    current_group: usize,
+}
+
+impl ObjParser {
+    fn new(path: &Path) -> ObjParser {
+        ObjParser {
+            path: path.to_path_buf(),
+            line: 0,
+            positions: vec![],
+            normals: vec![],
+            uvs: vec![],
+            materials: HashMap::new(),
+            groups: vec![FaceGroup {
+                material: None,
+                faces: vec![],
+            }],
+            current_group: 0,
+        }
+    }
+
+    fn error(&self, message: String) -> ObjError {
+        parse_error(&self.path, self.line, message)
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), ObjError> {
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            return Ok(());
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => {
+                // An optional weight w or a vertex colour may follow, neither is used
+                let values = parse_float_range(&args, 3, 7, &self.path, self.line)?;
+                self.positions
+                    .push(Point3::new(values[0], values[1], values[2]));
+            }
+            "vn" => {
+                let [x, y, z] = parse_floats(&args, &self.path, self.line)?;
+                self.normals.push(Vec3::new(x, y, z));
+            }
+            "vt" => {
+                // v defaults to 0 and the depth w of 3D textures is ignored
+                let values = parse_float_range(&args, 1, 3, &self.path, self.line)?;
+                self.uvs
+                    .push((values[0], values.get(1).copied().unwrap_or(0.0)));
+            }
+            "f" => self.parse_face(&args)?,
+            "usemtl" => {
+                let name = args.join(" ");
+                if !self.materials.contains_key(&name) {
+                    return Err(self.error(format!("unknown material '{}'", name)));
+                }
+                self.use_material(Some(name));
+            }
+            "mtllib" => {
+                let directory = self.path.parent().unwrap_or(Path::new("")).to_path_buf();
+                for library in &args {
+                    self.materials.extend(load_mtl(&directory.join(library))?);
+                }
+            }
+            // Groups, objects, smoothing groups and lines don't change how we build meshes
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn parse_face(&mut self, args: &[&str]) -> Result<(), ObjError> {
+        if args.len() < 3 {
+            return Err(self.error(format!(
+                "face needs at least 3 vertices, found {}",
+                args.len()
+            )));
+        }
+        let vertices = args
+            .iter()
+            .map(|arg| self.parse_vertex(arg))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let faces = &mut self.groups[self.current_group].faces;
+        for k in 1..vertices.len() - 1 {
+            faces.push([vertices[0], vertices[k], vertices[k + 1]]);
+        }
+        Ok(())
+    }
+
+    // One face vertex in `v`, `v/vt`, `v//vn` or `v/vt/vn` form
+    fn parse_vertex(&self, arg: &str) -> Result<VertexRef, ObjError> {
+        let mut parts = arg.split('/');
+        let position = self.resolve_index(parts.next(), self.positions.len(), "vertex")?;
+        let uv = match parts.next() {
+            None | Some("") => None,
+            index => Some(self.resolve_index(index, self.uvs.len(), "texture coordinate")?),
+        };
+        let normal = match parts.next() {
+            None | Some("") => None,
+            index => Some(self.resolve_index(index, self.normals.len(), "normal")?),
+        };
+        if parts.next().is_some() {
+            return Err(self.error(format!("malformed face vertex '{}'", arg)));
+        }
+        Ok(VertexRef {
+            position,
+            uv,
+            normal,
+        })
+    }
+
+    // OBJ indices are 1-based, negative ones count back from the last element seen so far
+    fn resolve_index(
+        &self,
+        index: Option<&str>,
+        len: usize,
+        kind: &str,
+    ) -> Result<usize, ObjError> {
+        let raw = index.unwrap_or("");
+        let value: i64 = raw
+            .parse()
+            .map_err(|_| self.error(format!("invalid {} index '{}'", kind, raw)))?;
+        let resolved = if value > 0 {
+            value - 1
+        } else {
+            len as i64 + value
+        };
+        if value == 0 || resolved < 0 || resolved >= len as i64 {
+            return Err(self.error(format!("{} index {} out of range", kind, value)));
+        }
+        Ok(resolved as usize)
+    }
+
+    fn use_material(&mut self, material: Option<String>) {
+        self.current_group = match self.groups.iter().position(|g| g.material == material) {
+            Some(index) => index,
+            None => {
+                self.groups.push(FaceGroup {
+                    material,
+                    faces: vec![],
+                });
+                self.groups.len() - 1
+            }
+        };
+    }
+
+    fn into_world(self) -> HittableList {
+        let mut world = HittableList::new();
+        for group in &self.groups {
+            if group.faces.is_empty() {
+                continue;
+            }
+            let material = match &group.material {
+                Some(name) => self.materials[name].to_material(),
+                None => MtlMaterial::default().to_material(),
+            };
+            world.add(Arc::new(TriangleMesh::new(
+                self.build_mesh(group),
+                material,
+            )));
+        }
+        world
+    }
+
+    fn build_mesh(&self, group: &FaceGroup) -> Mesh {
+        // Normals and uvs are only kept when every vertex of the group has one
+        let refs = || group.faces.iter().flatten();
+        let has_normals = refs().all(|r| r.normal.is_some());
+        let has_uvs = refs().all(|r| r.uv.is_some());
+
+        let mut mesh = Mesh::default();
+        let mut remap: HashMap<VertexRef, usize> = HashMap::new();
+        for face in &group.faces {
+            let mut triangle = [0; 3];
+            for (slot, vertex) in triangle.iter_mut().zip(face) {
+                *slot = *remap.entry(*vertex).or_insert_with(|| {
+                    mesh.positions.push(self.positions[vertex.position]);
+                    if let (true, Some(normal)) = (has_normals, vertex.normal) {
+                        mesh.normals.push(self.normals[normal]);
+                    }
+                    if let (true, Some(uv)) = (has_uvs, vertex.uv) {
+                        mesh.uvs.push(self.uvs[uv]);
+                    }
+                    mesh.positions.len() - 1
+                });
+            }
+            mesh.indices.push(triangle);
+        }
+        mesh
+    }
+}
+
+fn load_mtl(path: &Path) -> Result<HashMap<String, MtlMaterial>, ObjError> {
+    let mut materials = HashMap::new();
+    let mut current: Option<String> = None;
+
+    for_each_line(path, |line_number, line| {
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            return Ok(());
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            let name = args.join(" ");
+            if name.is_empty() {
+                return Err(parse_error(
+                    path,
+                    line_number,
+                    "missing material name".into(),
+                ));
+            }
+            materials.insert(name.clone(), MtlMaterial::default());
+            current = Some(name);
+            return Ok(());
+        }
+
+        let material = match &current {
+            Some(name) => materials.get_mut(name).unwrap(),
+            None => {
+                return Err(parse_error(
+                    path,
+                    line_number,
+                    format!("'{}' before any newmtl", keyword),
+                ))
+            }
+        };
+        match keyword {
+            "Kd" => {
+                let [r, g, b] = parse_floats(&args, path, line_number)?;
+                material.diffuse = Color::new(r, g, b);
+            }
+            "Ks" => {
+                let [r, g, b] = parse_floats(&args, path, line_number)?;
+                material.specular = Some(Color::new(r, g, b));
+            }
+            "Ke" => {
+                let [r, g, b] = parse_floats(&args, path, line_number)?;
+                material.emission = Color::new(r, g, b);
+            }
+            "Ns" => [material.shininess] = parse_floats(&args, path, line_number)?,
+            "Ni" => [material.optical_density] = parse_floats(&args, path, line_number)?,
+            "d" => [material.dissolve] = parse_floats(&args, path, line_number)?,
+            "Tr" => {
+                let [transparency] = parse_floats(&args, path, line_number)?;
+                material.dissolve = 1.0 - transparency;
+            }
+            "illum" => {
+                material.illum =
+                    args.first()
+                        .and_then(|arg| arg.parse().ok())
+                        .ok_or_else(|| {
+                            parse_error(path, line_number, "invalid illumination model".into())
+                        })?;
+            }
+            // Ambient and texture maps have no equivalent yet
+            _ => {}
+        }
+        Ok(())
+    })?;
+    Ok(materials)
+}
+
+fn for_each_line<F>(path: &Path, mut f: F) -> Result<(), ObjError>
+where
+    F: FnMut(usize, &str) -> Result<(), ObjError>,
+{
+    let io_error = |source| ObjError::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+    let reader = BufReader::new(File::open(path).map_err(io_error)?);
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(io_error)?;
+        // Strip comments
+        let line = line.split('#').next().unwrap_or("");
+        f(index + 1, line)?;
+    }
+    Ok(())
+}
+
+fn parse_floats<const N: usize>(
+    args: &[&str],
+    path: &Path,
+    line: usize,
+) -> Result<[f64; N], ObjError> {
+    if args.len() != N {
+        return Err(parse_error(
+            path,
+            line,
+            format!("expected {} numbers, found {}", N, args.len()),
+        ));
+    }
+    let mut values = [0.0; N];
+    for (value, arg) in values.iter_mut().zip(args) {
+        *value = arg
+            .parse()
+            .map_err(|_| parse_error(path, line, format!("invalid number '{}'", arg)))?;
+    }
+    Ok(values)
+}
+
+// Between `min` and `max` numbers, for lines whose trailing values are optional
+fn parse_float_range(
+    args: &[&str],
+    min: usize,
+    max: usize,
+    path: &Path,
+    line: usize,
+) -> Result<Vec<f64>, ObjError> {
+    if args.len() < min || args.len() > max {
+        return Err(parse_error(
+            path,
+            line,
+            format!("expected {} to {} numbers, found {}", min, max, args.len()),
+        ));
+    }
+    args.iter()
+        .map(|arg| {
+            arg.parse()
+                .map_err(|_| parse_error(path, line, format!("invalid number '{}'", arg)))
+        })
+        .collect()
+}
+
+fn parse_error(path: &Path, line: usize, message: String) -> ObjError {
+    ObjError::Parse {
+        path: path.to_path_buf(),
+        line,
+        message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::HitRecord;
+    use crate::ray::Ray;
+    use crate::sampler::{Sampler, SamplerKind};
+
+    // Parse OBJ text line by line as load_obj would, without the file
+    fn parse(text: &str) -> Result<ObjParser, ObjError> {
+        let mut parser = ObjParser::new(Path::new("test.obj"));
+        for (index, line) in text.lines().enumerate() {
+            parser.line = index + 1;
+            parser.parse_line(line)?;
+        }
+        Ok(parser)
+    }
+
+    fn positions(face: &[VertexRef; 3]) -> [usize; 3] {
+        face.map(|vertex| vertex.position)
+    }
+
+    const SQUARE: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n";
+
+    #[test]
+    fn vertices_take_optional_weights_and_colours() {
+        let parser =
+            parse("v 1 2 3\nv 1 2 3 1\nv 1 2 3 0.5 0.6 0.7\nv 1 2 3 1 0.5 0.6 0.7\n").unwrap();
+        assert_eq!(parser.positions, vec![Point3::new(1.0, 2.0, 3.0); 4]);
+        assert!(parse("v 1 2\n").is_err());
+        assert!(parse("v 1 2 3 4 5 6 7 8\n").is_err());
+    }
+
+    #[test]
+    fn texture_coordinates_take_one_to_three_numbers() {
+        let parser = parse("vt 0.5\nvt 0.25 0.75\nvt 0.25 0.75 0.1\n").unwrap();
+        assert_eq!(parser.uvs, vec![(0.5, 0.0), (0.25, 0.75), (0.25, 0.75)]);
+        assert!(parse("vt\n").is_err());
+    }
+
+    #[test]
+    fn polygons_are_triangulated_as_fans() {
+        let parser = parse(&format!("{}v 0.5 2 0\nf 1 2 3 5 4\n", SQUARE)).unwrap();
+        let faces: Vec<_> = parser.groups[0].faces.iter().map(positions).collect();
+        assert_eq!(faces, vec![[0, 1, 2], [0, 2, 4], [0, 4, 3]]);
+    }
+
+    #[test]
+    fn face_vertices_reference_uvs_and_normals() {
+        let text = format!("{}vt 0 0\nvt 1 0\nvn 0 0 1\nf 1/1/1 2/2/1 3//1\n", SQUARE);
+        let parser = parse(&text).unwrap();
+        let face = parser.groups[0].faces[0];
+        assert_eq!(
+            face[0],
+            VertexRef {
+                position: 0,
+                uv: Some(0),
+                normal: Some(0)
+            }
+        );
+        assert_eq!(face[1].uv, Some(1));
+        assert_eq!(face[2].uv, None);
+        assert_eq!(face[2].normal, Some(0));
+        assert!(parse(&format!("{}f 1/1/1/1 2 3\n", SQUARE)).is_err());
+    }
+
+    #[test]
+    fn negative_indices_count_back_from_the_last_vertex() {
+        let parser = parse(&format!("{}f -4 -3 -2\nv 5 5 5\nf -1 -2 -5\n", SQUARE)).unwrap();
+        let faces: Vec<_> = parser.groups[0].faces.iter().map(positions).collect();
+        assert_eq!(faces, vec![[0, 1, 2], [4, 3, 0]]);
+    }
+
+    #[test]
+    fn errors_report_the_line_number() {
+        let error = parse(&format!("{}\nf 1 2 7\n", SQUARE)).err().unwrap();
+        assert_eq!(error.to_string(), "test.obj:6: vertex index 7 out of range");
+
+        let error = parse("v 0 0 0\nf 1 0 1\n").err().unwrap();
+        assert_eq!(error.to_string(), "test.obj:2: vertex index 0 out of range");
+
+        let error = parse("# comment\nv 0 zero 0\n").err().unwrap();
+        assert_eq!(error.to_string(), "test.obj:2: invalid number 'zero'");
+    }
+
+    #[test]
+    fn emissive_mtl_materials_become_lights() {
+        let material = MtlMaterial {
+            emission: Color::new(4.0, 3.0, 2.0),
+            ..MtlMaterial::default()
+        }
+        .to_material();
+        let emitted = material.emitted(0.0, 0.0, &Point3::new(0.0, 0.0, 0.0));
+        assert_eq!(emitted, Color::new(4.0, 3.0, 2.0));
+    }
+
+    #[test]
+    fn mirrors_without_specular_colour_reflect_the_diffuse_colour() {
+        let material = MtlMaterial {
+            diffuse: Color::new(0.9, 0.6, 0.3),
+            illum: 3,
+            ..MtlMaterial::default()
+        }
+        .to_material();
+        let hit_record = HitRecord {
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            front_face: true,
+            ..HitRecord::default()
+        };
+        let ray = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let mut sampler = Sampler::new(SamplerKind::Independent, 1, 0, 0, 0);
+        let sample = material.sample(&ray, &hit_record, &mut sampler).unwrap();
+        assert_eq!(sample.weight, Color::new(0.9, 0.6, 0.3));
+    }
+}
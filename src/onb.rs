@@ -0,0 +1,46 @@
+use crate::vector::Vec3;
+
+// Orthonormal basis whose w axis is aligned with a given direction
+#[derive(Debug, Clone, Copy)]
+pub struct Onb {
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl Onb {
+    pub fn new(n: &Vec3) -> Onb {
+        let w = n.normalize();
+        // Any vector that isn't parallel to w will do for building the other two axes
+        let a = if w.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let v = w.cross(&a).normalize();
+        let u = w.cross(&v);
+        Onb { u, v, w }
+    }
+
+    pub fn u(&self) -> Vec3 {
+        self.u
+    }
+
+    pub fn v(&self) -> Vec3 {
+        self.v
+    }
+
+    pub fn w(&self) -> Vec3 {
+        self.w
+    }
+
+    // Convert a vector expressed in this basis to world coordinates
+    pub fn transform(&self, v: &Vec3) -> Vec3 {
+        self.u * v.x() + self.v * v.y() + self.w * v.z()
+    }
+
+    // Express a world space vector in this basis
+    pub fn to_local(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(v.dot(&self.u), v.dot(&self.v), v.dot(&self.w))
+    }
+}
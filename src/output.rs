@@ -0,0 +1,224 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::framebuffer::FrameBuffer;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    PpmAscii,  // P3, gamma corrected 8 bit text
+    PpmBinary, // P6, gamma corrected 8 bit
+    Png,       // gamma corrected 8 bit RGB
+    Pfm,       // linear 32 bit float
+    Exr,       // linear 32 bit float
+}
+
+impl ImageFormat {
+    // Guess the format from the file extension. `.ppm` is written as binary P6, use
+    // `ImageFormat::PpmAscii` explicitly to get the old text output.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<ImageFormat> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "ppm" => Some(ImageFormat::PpmBinary),
+            "png" => Some(ImageFormat::Png),
+            "pfm" => Some(ImageFormat::Pfm),
+            "exr" => Some(ImageFormat::Exr),
+            _ => None,
+        }
+    }
+}
+
+// Write the image as an ASCII (P3) PPM, gamma corrected to 8 bits per channel
+pub fn write_ppm<W: Write>(image: &FrameBuffer, writer: &mut W) -> io::Result<()> {
+    writeln!(writer, "P3\n{} {}\n255", image.width(), image.height())?;
+    for pixel in image.pixels() {
+        write!(writer, "{}", pixel.to_string())?;
+    }
+    writer.flush()
+}
+
+// Write the image as a binary (P6) PPM, gamma corrected to 8 bits per channel
+pub fn write_ppm_binary<W: Write>(image: &FrameBuffer, writer: &mut W) -> io::Result<()> {
+    writeln!(writer, "P6\n{} {}\n255", image.width(), image.height())?;
+    writer.write_all(&to_rgb8(image))?;
+    writer.flush()
+}
+
+pub fn write_png<W: Write>(image: &FrameBuffer, writer: &mut W) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(&mut *writer, image.width(), image.height());
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_source_gamma(png::ScaledFloat::new(1.0 / 2.0));
+
+    let mut png_writer = encoder.write_header()?;
+    png_writer.write_image_data(&to_rgb8(image))?;
+    png_writer.finish()?;
+    writer.flush()
+}
+
+// Portable float map: linear values, little endian, rows stored bottom to top
+pub fn write_pfm<W: Write>(image: &FrameBuffer, writer: &mut W) -> io::Result<()> {
+    writeln!(writer, "PF\n{} {}\n-1.0", image.width(), image.height())?;
+    let width = image.width() as usize;
+    for row in image.pixels().chunks(width).rev() {
+        for pixel in row {
+            for component in [pixel.x(), pixel.y(), pixel.z()] {
+                writer.write_all(&(component as f32).to_le_bytes())?;
+            }
+        }
+    }
+    writer.flush()
+}
+
+// OpenEXR needs a seekable target, so this one only writes straight to a file
+pub fn write_exr<P: AsRef<Path>>(image: &FrameBuffer, path: P) -> io::Result<()> {
+    exr::prelude::write_rgb_file(
+        path,
+        image.width() as usize,
+        image.height() as usize,
+        |x, y| {
+            let pixel = image.get_pixel(x as u32, y as u32);
+            (pixel.x() as f32, pixel.y() as f32, pixel.z() as f32)
+        },
+    )
+    .map_err(io::Error::other)
+}
+
+// Write the image to a file, choosing the format from the extension
+pub fn save<P: AsRef<Path>>(image: &FrameBuffer, path: P) -> io::Result<()> {
+    let format = ImageFormat::from_path(&path).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "unsupported image extension for {}",
+                path.as_ref().display()
+            ),
+        )
+    })?;
+    save_as(image, path, format)
+}
+
+pub fn save_as<P: AsRef<Path>>(
+    image: &FrameBuffer,
+    path: P,
+    format: ImageFormat,
+) -> io::Result<()> {
+    match format {
+        ImageFormat::PpmAscii => write_ppm(image, &mut create(path)?),
+        ImageFormat::PpmBinary => write_ppm_binary(image, &mut create(path)?),
+        ImageFormat::Png => write_png(image, &mut create(path)?),
+        ImageFormat::Pfm => write_pfm(image, &mut create(path)?),
+        ImageFormat::Exr => write_exr(image, path),
+    }
+}
+
+fn create<P: AsRef<Path>>(path: P) -> io::Result<BufWriter<File>> {
+    Ok(BufWriter::new(File::create(path)?))
+}
+
+fn to_rgb8(image: &FrameBuffer) -> Vec<u8> {
+    image
+        .pixels()
+        .iter()
+        .flat_map(|pixel| pixel.to_bytes())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    // 2x2 image with a different colour in every corner
+    fn image() -> FrameBuffer {
+        let mut image = FrameBuffer::new(2, 2);
+        image.set_pixel(0, 0, Color::new(0.0, 0.25, 1.0));
+        image.set_pixel(1, 0, Color::new(1.0, 0.0, 0.0));
+        image.set_pixel(0, 1, Color::new(0.0, 4.0, -1.0));
+        image.set_pixel(1, 1, Color::new(0.5, 0.0625, 0.01));
+        image
+    }
+
+    #[test]
+    fn ppm_ascii_output() {
+        let mut bytes = vec![];
+        write_ppm(&image(), &mut bytes).unwrap();
+        let text = String::from_utf8(bytes).unwrap();
+        assert_eq!(
+            text,
+            "P3\n2 2\n255\n0 127 255\n255 0 0\n0 255 0\n180 63 25\n"
+        );
+    }
+
+    #[test]
+    fn ppm_binary_output() {
+        let mut bytes = vec![];
+        write_ppm_binary(&image(), &mut bytes).unwrap();
+        let header = b"P6\n2 2\n255\n";
+        assert_eq!(&bytes[..header.len()], header);
+        assert_eq!(
+            &bytes[header.len()..],
+            &[0, 127, 255, 255, 0, 0, 0, 255, 0, 180, 63, 25]
+        );
+    }
+
+    #[test]
+    fn pfm_output_is_little_endian_and_bottom_up() {
+        let mut bytes = vec![];
+        write_pfm(&image(), &mut bytes).unwrap();
+        // A negative scale marks little endian data
+        let header = b"PF\n2 2\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+
+        let floats: Vec<f32> = bytes[header.len()..]
+            .chunks_exact(4)
+            .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        // Unclamped linear values, starting with the bottom row
+        let expected = [
+            0.0, 4.0, -1.0, 0.5, 0.0625, 0.01, // bottom row
+            0.0, 0.25, 1.0, 1.0, 0.0, 0.0, // top row
+        ];
+        assert_eq!(floats, expected);
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let mut bytes = vec![];
+        write_png(&image(), &mut bytes).unwrap();
+
+        let decoder = png::Decoder::new(bytes.as_slice());
+        let mut reader = decoder.read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).unwrap();
+        assert_eq!((info.width, info.height), (2, 2));
+        assert_eq!(info.color_type, png::ColorType::Rgb);
+        assert_eq!(
+            &data[..info.buffer_size()],
+            &[0, 127, 255, 255, 0, 0, 0, 255, 0, 180, 63, 25]
+        );
+    }
+
+    #[test]
+    fn exr_round_trip() {
+        let path = std::env::temp_dir().join(format!("output-test-{}.exr", std::process::id()));
+        write_exr(&image(), &path).unwrap();
+        let read = exr::prelude::read_first_rgba_layer_from_file(
+            &path,
+            |resolution, _| vec![vec![[0.0f32; 3]; resolution.width()]; resolution.height()],
+            |pixels, position, (r, g, b, _): (f32, f32, f32, f32)| {
+                pixels[position.y()][position.x()] = [r, g, b]
+            },
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        let pixels = read.unwrap().layer_data.channel_data.pixels;
+        assert_eq!(
+            pixels,
+            [
+                [[0.0, 0.25, 1.0], [1.0, 0.0, 0.0]],
+                [[0.0, 4.0, -1.0], [0.5, 0.0625, 0.01]],
+            ]
+        );
+    }
+}
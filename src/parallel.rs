@@ -0,0 +1,46 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::thread;
+
+// Run `work` on every item using `threads` scoped threads. Each thread starts on its own
+// contiguous share of the items, front to back, and once that runs out steals from the back of
+// another thread's share, so threads that finish early keep busy without fighting over
+// neighbouring items.
+pub fn for_each_stealing<T, F>(items: Vec<T>, threads: usize, work: F)
+where
+    T: Send,
+    F: Fn(T) + Sync,
+{
+    let threads = threads.clamp(1, items.len().max(1));
+    if threads == 1 {
+        items.into_iter().for_each(work);
+        return;
+    }
+
+    let share = items.len().div_ceil(threads);
+    let mut items = items.into_iter();
+    let queues: Vec<Mutex<VecDeque<T>>> = (0..threads)
+        .map(|_| Mutex::new(items.by_ref().take(share).collect()))
+        .collect();
+
+    thread::scope(|scope| {
+        for worker in 0..threads {
+            let (queues, work) = (&queues, &work);
+            scope.spawn(move || {
+                while let Some(item) = next_item(queues, worker) {
+                    work(item);
+                }
+            });
+        }
+    });
+}
+
+// Items are never added back, so once every queue is empty the work is done
+fn next_item<T>(queues: &[Mutex<VecDeque<T>>], worker: usize) -> Option<T> {
+    if let Some(item) = queues[worker].lock().unwrap().pop_front() {
+        return Some(item);
+    }
+    (1..queues.len())
+        .map(|offset| (worker + offset) % queues.len())
+        .find_map(|victim| queues[victim].lock().unwrap().pop_back())
+}
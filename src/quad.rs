@@ -0,0 +1,143 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::Aabb,
+    constants::INFINITY,
+    hittable::{HitRecord, Hittable, HittableList},
+    interval::Interval,
+    material::Material,
+    ray::Ray,
+    utils::{random_f64, Rng},
+    vector::{Point3, Vec3},
+};
+
+// Parallelogram with corner q and edges u and v
+pub struct Quad {
+    q: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3, // Projects a point in the plane onto (alpha, beta) coordinates
+    normal: Vec3,
+    d: f64,
+    area: f64,
+    pub material: Arc<dyn Material>,
+    bbox: Aabb,
+}
+
+impl Quad {
+    pub fn new(q: Point3, u: Vec3, v: Vec3, material: Arc<dyn Material>) -> Quad {
+        let n = u.cross(&v);
+        let normal = n.normalize();
+        let d = normal.dot(&q);
+        let w = n / n.dot(&n);
+        let area = n.length();
+
+        let bbox_diagonal1 = Aabb::from_points(&q, &(q + u + v));
+        let bbox_diagonal2 = Aabb::from_points(&(q + u), &(q + v));
+        let bbox = Aabb::enclosing(&bbox_diagonal1, &bbox_diagonal2);
+
+        Quad {
+            q,
+            u,
+            v,
+            w,
+            normal,
+            d,
+            area,
+            material,
+            bbox,
+        }
+    }
+
+    pub fn corner(&self) -> Point3 {
+        self.q
+    }
+
+    pub fn edges(&self) -> (Vec3, Vec3) {
+        (self.u, self.v)
+    }
+}
+
+impl Hittable for Quad {
+    fn hit<'a>(&'a self, ray: &Ray, ray_t: Interval, rec: &mut HitRecord<'a>) -> bool {
+        let denom = self.normal.dot(ray.direction());
+
+        // Ray is parallel to the plane
+        if denom.abs() < 1e-8 {
+            return false;
+        }
+
+        let t = (self.d - self.normal.dot(ray.origin())) / denom;
+        if !ray_t.contains(t) {
+            return false;
+        }
+
+        let intersection = ray.at(t);
+        let planar_hitpt = intersection - self.q;
+        let alpha = self.w.dot(&planar_hitpt.cross(&self.v));
+        let beta = self.w.dot(&self.u.cross(&planar_hitpt));
+
+        let unit_interval = Interval::new(0.0, 1.0);
+        if !unit_interval.contains(alpha) || !unit_interval.contains(beta) {
+            return false;
+        }
+
+        rec.t = t;
+        rec.p = intersection;
+        rec.u = alpha;
+        rec.v = beta;
+        rec.set_face_normal(ray, &self.normal);
+        rec.material = self.material.as_ref();
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let mut rec = HitRecord::default();
+        if !self.hit(
+            &Ray::new(*origin, *direction),
+            Interval::new(0.001, INFINITY),
+            &mut rec,
+        ) {
+            return 0.0;
+        }
+
+        // Convert the uniform area density to solid angle
+        let distance_squared = rec.t * rec.t * direction.length_squared();
+        let cosine = (direction.dot(&rec.normal) / direction.length()).abs();
+        distance_squared / (cosine * self.area)
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut Rng) -> Vec3 {
+        let p = self.q + (self.u * random_f64(rng)) + (self.v * random_f64(rng));
+        p - *origin
+    }
+}
+
+// Axis aligned box with opposite corners a and b, built from six outward facing quads
+pub fn make_box(a: &Point3, b: &Point3, material: Arc<dyn Material>) -> HittableList {
+    let mut sides = HittableList::new();
+
+    let min = Point3::new(a.x().min(b.x()), a.y().min(b.y()), a.z().min(b.z()));
+    let max = Point3::new(a.x().max(b.x()), a.y().max(b.y()), a.z().max(b.z()));
+
+    let dx = Vec3::new(max.x() - min.x(), 0.0, 0.0);
+    let dy = Vec3::new(0.0, max.y() - min.y(), 0.0);
+    let dz = Vec3::new(0.0, 0.0, max.z() - min.z());
+
+    let sides_desc = [
+        (Point3::new(min.x(), min.y(), max.z()), dx, dy), // front
+        (Point3::new(max.x(), min.y(), max.z()), dz * -1.0, dy), // right
+        (Point3::new(max.x(), min.y(), min.z()), dx * -1.0, dy), // back
+        (Point3::new(min.x(), min.y(), min.z()), dz, dy), // left
+        (Point3::new(min.x(), max.y(), max.z()), dx, dz * -1.0), // top
+        (Point3::new(min.x(), min.y(), min.z()), dx, dz), // bottom
+    ];
+    for (q, u, v) in sides_desc {
+        sides.add(Arc::new(Quad::new(q, u, v, material.clone())));
+    }
+    sides
+}
@@ -1,19 +1,32 @@
+use crate::background::Background;
 use crate::color::Color;
 use crate::constants::INFINITY;
 use crate::hittable::HitRecord;
 use crate::hittable::Hittable;
+use crate::hittable::HittableList;
 use crate::interval::Interval;
+use crate::sampler::Sampler;
 use crate::vector::{Point3, Vec3};
 
 #[derive(Default, Clone, Copy)]
 pub struct Ray {
     origin: Point3,
     direction: Vec3,
+    // Wavelength in nanometres carried by spectral renders, None for ordinary RGB ones
+    wavelength: Option<f64>,
 }
 
 impl Ray {
     pub fn new(origin: Point3, direction: Vec3) -> Ray {
-        Ray { origin, direction }
+        Ray {
+            origin,
+            direction,
+            wavelength: None,
+        }
+    }
+
+    pub fn with_wavelength(self, wavelength: Option<f64>) -> Ray {
+        Ray { wavelength, ..self }
     }
 
     pub fn origin(&self) -> &Point3 {
@@ -24,33 +37,114 @@ impl Ray {
         &self.direction
     }
 
+    pub fn wavelength(&self) -> Option<f64> {
+        self.wavelength
+    }
+
     pub fn at(&self, t: f64) -> Point3 {
-        &self.origin + &(&self.direction * t)
+        self.origin + self.direction * t
     }
 
-    pub fn color<T>(&self, depth: u32, world: &T) -> Color
+    // Path trace the ray. When `lights` isn't empty every bounce also samples a point on a light
+    // directly, and the two ways of reaching a light are combined with multiple
+    // importance sampling.
+    pub fn color<T>(
+        &self,
+        depth: u32,
+        world: &T,
+        lights: &HittableList,
+        background: &Background,
+        sampler: &mut Sampler,
+    ) -> Color
     where
         T: Hittable,
     {
-        if depth <= 0 {
-            return Color::new(0.0, 0.0, 0.0);
-        }
+        let mut ray = *self;
+        let mut radiance = Color::new(0.0, 0.0, 0.0);
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        // Density of the bounce that produced `ray`, None for camera rays and specular bounces
+        let mut bounce_pdf: Option<f64> = None;
 
-        let mut hit_record = HitRecord::default();
-
-        if world.hit(self, Interval::new(0.001, INFINITY), &mut hit_record) {
-            let mut scattered = Ray::default();
-            let mut attenuation = Color::default();
+        for _ in 0..depth {
+            let mut hit_record = HitRecord::default();
+            if !world.hit(&ray, Interval::new(0.001, INFINITY), &mut hit_record) {
+                radiance += throughput * background.color(&ray);
+                break;
+            }
 
-            if hit_record
+            let emitted = hit_record
                 .material
-                .scatter(self, &hit_record, &mut attenuation, &mut scattered)
-            {
-                return attenuation * scattered.color(depth - 1, world);
+                .emitted(hit_record.u, hit_record.v, &hit_record.p);
+            let weight = match bounce_pdf {
+                Some(pdf) => power_heuristic(pdf, lights.pdf_value(ray.origin(), ray.direction())),
+                None => 1.0,
+            };
+            radiance += throughput * emitted * weight;
+
+            // Light the whole BSDF before sampling it, so directions the sampling rejects or
+            // specular parts of the material don't lose the direct light. Purely specular
+            // materials evaluate to black and get nothing from it.
+            if !lights.is_empty() {
+                radiance += throughput * sample_light(&ray, &hit_record, world, lights, sampler);
             }
+
+            let Some(scatter) = hit_record.material.sample(&ray, &hit_record, sampler) else {
+                break;
+            };
+            bounce_pdf = (scatter.pdf > 0.0 && !lights.is_empty()).then_some(scatter.pdf);
+
+            throughput = throughput * scatter.weight;
+            ray = Ray::new(hit_record.p, scatter.direction).with_wavelength(ray.wavelength());
         }
-        let unit_direction = &self.direction.normalize();
-        let t = 0.5 * (unit_direction.y() + 1.0);
-        Color::new(1.0, 1.0, 1.0) * (1.0 - t) + Color::new(0.5, 0.7, 1.0) * t
+        radiance
     }
 }
+
+// Light arriving at the hit point from a direction picked on one of the lights, weighted against
+// the chance of the material's own sampling finding that light
+fn sample_light<T>(
+    ray_in: &Ray,
+    hit_record: &HitRecord,
+    world: &T,
+    lights: &HittableList,
+    sampler: &mut Sampler,
+) -> Color
+where
+    T: Hittable,
+{
+    let black = Color::new(0.0, 0.0, 0.0);
+    let shadow_ray = Ray::new(hit_record.p, lights.random(&hit_record.p, sampler.rng()))
+        .with_wavelength(ray_in.wavelength());
+    let light_pdf = lights.pdf_value(shadow_ray.origin(), shadow_ray.direction());
+    if light_pdf <= 0.0 {
+        return black;
+    }
+
+    let direction = shadow_ray.direction().normalize();
+    let scattering_pdf = hit_record.material.pdf(ray_in, hit_record, &direction);
+    if scattering_pdf <= 0.0 {
+        return black;
+    }
+
+    let mut light_record = HitRecord::default();
+    if !world.hit(
+        &shadow_ray,
+        Interval::new(0.001, INFINITY),
+        &mut light_record,
+    ) {
+        return black;
+    }
+    let emitted = light_record
+        .material
+        .emitted(light_record.u, light_record.v, &light_record.p);
+
+    let bsdf = hit_record.material.eval(ray_in, hit_record, &direction);
+    let cos_theta = hit_record.normal.dot(&direction).abs();
+    bsdf * emitted * (cos_theta * power_heuristic(light_pdf, scattering_pdf) / light_pdf)
+}
+
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let a = pdf * pdf;
+    let b = other_pdf * other_pdf;
+    a / (a + b)
+}
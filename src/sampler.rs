@@ -0,0 +1,319 @@
+use crate::utils::{random_f64, seeded_rng, splitmix64, Rng};
+
+// How the sample points of a pixel are spread over each dimension of the path
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SamplerKind {
+    // Uniform random numbers, nothing shared between samples
+    #[default]
+    Independent,
+    // One jittered point per cell of a grid, with the cells shuffled differently per dimension
+    Stratified,
+    // Halton sequence with a prime base per dimension, digits Owen scrambled per pixel
+    Halton,
+    // Owen scrambled Sobol (0, 2) sequence, shuffled differently per pair of dimensions
+    Sobol,
+}
+
+// First primes, the Halton bases. Later dimensions fall back to independent samples.
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+// Largest f64 below 1, so scaled integers never round up to exactly 1
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+// Sample values for one camera sample of one pixel. Every call to `get_1d` or `get_2d` moves on
+// to the next dimension, so the camera and the materials must ask in the same order for every
+// sample. Numbers that don't benefit from stratification come from `rng`.
+pub struct Sampler {
+    kind: SamplerKind,
+    samples_per_pixel: u32,
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dimension: u32,
+    rng: Rng,
+}
+
+impl Sampler {
+    // Sample `index` out of `samples_per_pixel` for the pixel numbered `pixel`
+    pub fn new(
+        kind: SamplerKind,
+        samples_per_pixel: u32,
+        seed: u64,
+        pixel: u64,
+        index: u32,
+    ) -> Sampler {
+        Sampler {
+            kind,
+            samples_per_pixel: samples_per_pixel.max(1),
+            seed,
+            pixel,
+            index,
+            dimension: 0,
+            // Every sample gets its own stream so results don't depend on which thread ran it
+            rng: seeded_rng(seed, (pixel << 32) | index as u64),
+        }
+    }
+
+    pub fn rng(&mut self) -> &mut Rng {
+        &mut self.rng
+    }
+
+    pub fn get_1d(&mut self) -> f64 {
+        let dimension = self.next_dimensions(1);
+        match self.kind {
+            SamplerKind::Independent => random_f64(&mut self.rng),
+            SamplerKind::Stratified => {
+                let n = self.samples_per_pixel;
+                let stratum = permutation_element(self.index, n, self.hash(dimension));
+                (stratum as f64 + random_f64(&mut self.rng)) / n as f64
+            }
+            SamplerKind::Halton => self.halton(dimension),
+            SamplerKind::Sobol => self.sobol(dimension).0,
+        }
+    }
+
+    pub fn get_2d(&mut self) -> (f64, f64) {
+        let dimension = self.next_dimensions(2);
+        match self.kind {
+            SamplerKind::Independent => (random_f64(&mut self.rng), random_f64(&mut self.rng)),
+            SamplerKind::Stratified => {
+                // As square a grid as the sample count allows, with spare cells left empty
+                let n = self.samples_per_pixel;
+                let nx = (n as f64).sqrt().ceil() as u32;
+                let ny = n.div_ceil(nx);
+                let cell = permutation_element(self.index, nx * ny, self.hash(dimension));
+                let x = (cell % nx) as f64 + random_f64(&mut self.rng);
+                let y = (cell / nx) as f64 + random_f64(&mut self.rng);
+                (x / nx as f64, y / ny as f64)
+            }
+            SamplerKind::Halton => (self.halton(dimension), self.halton(dimension + 1)),
+            SamplerKind::Sobol => self.sobol(dimension),
+        }
+    }
+
+    fn next_dimensions(&mut self, count: u32) -> u32 {
+        let dimension = self.dimension;
+        self.dimension += count;
+        dimension
+    }
+
+    // Hash of the seed, pixel and dimension, shared by every sample of the pixel
+    fn hash(&self, dimension: u32) -> u64 {
+        splitmix64(self.seed ^ splitmix64(self.pixel ^ splitmix64(dimension as u64)))
+    }
+
+    fn halton(&mut self, dimension: u32) -> f64 {
+        let Some(&base) = PRIMES.get(dimension as usize) else {
+            return random_f64(&mut self.rng);
+        };
+        scrambled_radical_inverse(self.index, base, self.hash(dimension))
+    }
+
+    // Burley's shuffled, Owen scrambled Sobol points. The first two Sobol dimensions are a
+    // (0, 2) sequence; shuffling the index per dimension pair keeps the pairs independent.
+    fn sobol(&self, dimension: u32) -> (f64, f64) {
+        let seed = self.hash(dimension) as u32;
+        let index = nested_uniform_scramble(self.index, seed);
+        let x = nested_uniform_scramble(index.reverse_bits(), hash_combine(seed, 0));
+        let y = nested_uniform_scramble(sobol_second(index), hash_combine(seed, 1));
+        (to_unit(x), to_unit(y))
+    }
+}
+
+// Digits of `index` in `base` mirrored around the decimal point, each digit permuted depending
+// on the digits before it. Without the scrambling the few samples of a pixel would only reach
+// the first digit or two in large bases and bunch up near zero.
+fn scrambled_radical_inverse(mut index: u32, base: u32, seed: u64) -> f64 {
+    // Enough digits for 32 bits of precision
+    let digits = (32.0 / (base as f64).log2()).ceil() as u32;
+    let inverse_base = 1.0 / base as f64;
+    let mut reversed = 0u64;
+    let mut inverse_base_n = 1.0;
+    for _ in 0..digits {
+        let next = index / base;
+        let digit = index - next * base;
+        let digit = permutation_element(digit, base, splitmix64(seed ^ reversed));
+        reversed = reversed * base as u64 + digit as u64;
+        inverse_base_n *= inverse_base;
+        index = next;
+    }
+    (reversed as f64 * inverse_base_n).min(ONE_MINUS_EPSILON)
+}
+
+// Second Sobol dimension, whose direction numbers form the Pascal matrix mod 2
+fn sobol_second(index: u32) -> u32 {
+    let mut result = 0;
+    let mut direction = 1u32 << 31;
+    for bit in 0..32 {
+        if index >> bit & 1 == 1 {
+            result ^= direction;
+        }
+        direction ^= direction >> 1;
+    }
+    result
+}
+
+// Owen scrambling via the Laine-Karras hash, with Burley's constants
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x.reverse_bits()
+}
+
+fn hash_combine(seed: u32, value: u32) -> u32 {
+    seed ^ (value
+        .wrapping_add(0x9e37_79b9)
+        .wrapping_add(seed << 6)
+        .wrapping_add(seed >> 2))
+}
+
+fn to_unit(x: u32) -> f64 {
+    (x as f64 / (1u64 << 32) as f64).min(ONE_MINUS_EPSILON)
+}
+
+// Element i of a random permutation of 0..n picked by `seed`, without building the permutation
+// (Kensler, "Correlated Multi-Jittered Sampling")
+fn permutation_element(i: u32, n: u32, seed: u64) -> u32 {
+    let p = seed as u32;
+    let mut w = n - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    let mut i = i;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [SamplerKind; 4] = [
+        SamplerKind::Independent,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+    ];
+
+    // The first `dimensions` values of one sample, asking for 1D and 2D values in turn
+    fn values(sampler: &mut Sampler, dimensions: u32) -> Vec<f64> {
+        let mut values = vec![];
+        while (values.len() as u32) < dimensions {
+            if values.len() % 3 == 0 {
+                values.push(sampler.get_1d());
+            } else {
+                let (x, y) = sampler.get_2d();
+                values.extend([x, y]);
+            }
+        }
+        values
+    }
+
+    #[test]
+    fn every_dimension_is_in_unit_interval() {
+        for kind in KINDS {
+            for samples_per_pixel in [1, 5, 16, 64] {
+                for pixel in 0..16 {
+                    for index in 0..samples_per_pixel {
+                        let mut sampler = Sampler::new(kind, samples_per_pixel, 3, pixel, index);
+                        // More dimensions than there are Halton bases
+                        for value in values(&mut sampler, 100) {
+                            assert!((0.0..1.0).contains(&value), "{:?} gave {}", kind, value);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stratified_1d_puts_one_sample_in_each_stratum() {
+        for n in [1, 2, 7, 16, 33] {
+            for pixel in 0..8 {
+                for dimension in 0..6 {
+                    let mut hits = vec![0; n as usize];
+                    for index in 0..n {
+                        let mut sampler = Sampler::new(SamplerKind::Stratified, n, 5, pixel, index);
+                        for _ in 0..dimension {
+                            sampler.get_1d();
+                        }
+                        hits[(sampler.get_1d() * n as f64) as usize] += 1;
+                    }
+                    assert!(hits.iter().all(|&h| h == 1), "{} samples: {:?}", n, hits);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stratified_2d_puts_at_most_one_sample_in_each_cell() {
+        for n in [1u32, 4, 6, 16, 20] {
+            let nx = (n as f64).sqrt().ceil() as u32;
+            let ny = n.div_ceil(nx);
+            for pixel in 0..8 {
+                for dimension in 0..4 {
+                    let mut hits = vec![0; (nx * ny) as usize];
+                    for index in 0..n {
+                        let mut sampler = Sampler::new(SamplerKind::Stratified, n, 5, pixel, index);
+                        for _ in 0..dimension {
+                            sampler.get_2d();
+                        }
+                        let (x, y) = sampler.get_2d();
+                        let cell = (y * ny as f64) as u32 * nx + (x * nx as f64) as u32;
+                        hits[cell as usize] += 1;
+                    }
+                    assert!(hits.iter().all(|&h| h <= 1), "{} samples: {:?}", n, hits);
+                    assert_eq!(hits.iter().sum::<u32>(), n);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fixed_seeds_are_reproducible() {
+        for kind in KINDS {
+            let sample = |seed| {
+                let mut sampler = Sampler::new(kind, 16, seed, 42, 3);
+                let mut values = values(&mut sampler, 80);
+                values.push(random_f64(sampler.rng()));
+                values
+            };
+            assert_eq!(sample(9), sample(9), "{:?}", kind);
+            assert_ne!(sample(9), sample(10), "{:?}", kind);
+        }
+    }
+}
@@ -0,0 +1,1016 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::{
+    background::Background,
+    camera::Camera,
+    color::Color,
+    filter::Filter,
+    hittable::{Hittable, HittableList},
+    material::{
+        Conductor, ConductorPreset, Dielectric, DiffuseLight, Lambertian, Material, Metal,
+        MixMaterial, Principled,
+    },
+    matrix::Mat4,
+    obj::{load_obj, ObjError},
+    quad::{make_box, Quad},
+    sampler::SamplerKind,
+    spectrum::Dispersion,
+    sphere::Sphere,
+    texture::{CheckerTexture, ImageTexture, NoisePattern, NoiseTexture, SolidColor, Texture},
+    transform::{SingularMatrix, Transform},
+    triangle::Triangle,
+    vector::Vec3,
+};
+
+// A world ready to render and the camera configured to look at it
+pub struct Scene {
+    pub world: HittableList,
+    // Emissive objects, sampled directly by the renderer
+    pub lights: HittableList,
+    pub camera: Camera,
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    UnknownMaterial {
+        path: PathBuf,
+        object: usize,
+        name: String,
+    },
+    UnknownTexture {
+        path: PathBuf,
+        material: String,
+        name: String,
+    },
+    UnknownComponent {
+        path: PathBuf,
+        material: String,
+        name: String,
+    },
+    MaterialCycle {
+        path: PathBuf,
+        material: String,
+    },
+    Transform {
+        path: PathBuf,
+        object: usize,
+        source: SingularMatrix,
+    },
+    InvalidCamera {
+        path: PathBuf,
+        field: &'static str,
+        expected: &'static str,
+    },
+    Obj(ObjError),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            SceneError::Parse { path, source } => write!(f, "{}: {}", path.display(), source),
+            SceneError::UnknownMaterial { path, object, name } => write!(
+                f,
+                "{}: object {} uses unknown material '{}'",
+                path.display(),
+                object,
+                name
+            ),
+            SceneError::UnknownTexture {
+                path,
+                material,
+                name,
+            } => write!(
+                f,
+                "{}: material '{}' uses unknown texture '{}'",
+                path.display(),
+                material,
+                name
+            ),
+            SceneError::UnknownComponent {
+                path,
+                material,
+                name,
+            } => write!(
+                f,
+                "{}: material '{}' mixes unknown material '{}'",
+                path.display(),
+                material,
+                name
+            ),
+            SceneError::MaterialCycle { path, material } => write!(
+                f,
+                "{}: material '{}' is mixed into itself",
+                path.display(),
+                material
+            ),
+            SceneError::Transform {
+                path,
+                object,
+                source,
+            } => write!(f, "{}: object {}: {}", path.display(), object, source),
+            SceneError::InvalidCamera {
+                path,
+                field,
+                expected,
+            } => write!(
+                f,
+                "{}: camera {} must be {}",
+                path.display(),
+                field,
+                expected
+            ),
+            SceneError::Obj(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SceneError::Io { source, .. } => Some(source),
+            SceneError::Parse { source, .. } => Some(source),
+            SceneError::Transform { source, .. } => Some(source),
+            SceneError::UnknownMaterial { .. }
+            | SceneError::UnknownTexture { .. }
+            | SceneError::UnknownComponent { .. }
+            | SceneError::MaterialCycle { .. }
+            | SceneError::InvalidCamera { .. } => None,
+            SceneError::Obj(error) => Some(error),
+        }
+    }
+}
+
+impl From<ObjError> for SceneError {
+    fn from(error: ObjError) -> Self {
+        SceneError::Obj(error)
+    }
+}
+
+// Load a TOML scene description. Mesh and image paths are resolved relative to the scene file.
+// Material colours are either an [r, g, b] triple or the name of a texture.
+// Objects are placed elsewhere by wrapping them in an `instance` with a translation, rotation
+// and scale.
+//
+//     [camera]
+//     image_width = 400
+//     look_from = [13.0, 2.0, 3.0]
+//
+//     [textures.checker]
+//     type = "checker"
+//     scale = 0.32
+//     even = [0.2, 0.3, 0.1]
+//     odd = [0.9, 0.9, 0.9]
+//
+//     [materials.ground]
+//     type = "lambertian"
+//     albedo = "checker"
+//
+//     [[objects]]
+//     type = "sphere"
+//     center = [0.0, -1000.0, 0.0]
+//     radius = 1000.0
+//     material = "ground"
+pub fn load_scene<P: AsRef<Path>>(path: P) -> Result<Scene, SceneError> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path).map_err(|source| SceneError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    parse_scene(&text, path)
+}
+
+// Scene from the text of a file at `path`, which errors and relative paths refer to
+fn parse_scene(text: &str, path: &Path) -> Result<Scene, SceneError> {
+    let description: SceneDescription =
+        toml::from_str(text).map_err(|source| SceneError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })?;
+    description.build(path)
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDescription {
+    #[serde(default)]
+    camera: CameraDescription,
+    #[serde(default)]
+    textures: HashMap<String, TextureDescription>,
+    #[serde(default)]
+    materials: HashMap<String, MaterialDescription>,
+    #[serde(default)]
+    objects: Vec<ObjectDescription>,
+}
+
+// Every field is optional and falls back to Camera::new()
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDescription {
+    aspect_ratio: Option<f64>,
+    image_width: Option<u32>,
+    samples_per_pixel: Option<u32>,
+    min_samples_per_pixel: Option<u32>,
+    adaptive_threshold: Option<f64>,
+    sampler: Option<SamplerDescription>,
+    filter: Option<FilterDescription>,
+    spectral: Option<bool>,
+    max_depth: Option<u32>,
+    vfov: Option<f64>,
+    look_from: Option<[f64; 3]>,
+    look_at: Option<[f64; 3]>,
+    vup: Option<[f64; 3]>,
+    defocus_angle: Option<f64>,
+    focus_dist: Option<f64>,
+    seed: Option<u64>,
+    background: Option<BackgroundDescription>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SamplerDescription {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+// Parameters left out take the values of the Filter constants
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum FilterDescription {
+    Box {
+        radius: Option<f64>,
+    },
+    Tent {
+        radius: Option<f64>,
+    },
+    Gaussian {
+        radius: Option<f64>,
+        sigma: Option<f64>,
+    },
+    Mitchell {
+        radius: Option<f64>,
+        b: Option<f64>,
+        c: Option<f64>,
+    },
+    Lanczos {
+        radius: Option<f64>,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum BackgroundDescription {
+    Solid { color: [f64; 3] },
+    Gradient { bottom: [f64; 3], top: [f64; 3] },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDescription {
+    Solid {
+        color: [f64; 3],
+    },
+    Checker {
+        scale: f64,
+        even: [f64; 3],
+        odd: [f64; 3],
+    },
+    Image {
+        path: PathBuf,
+    },
+    Noise {
+        #[serde(default)]
+        pattern: NoisePatternDescription,
+        scale: f64,
+        #[serde(default)]
+        seed: u64,
+        #[serde(default)]
+        low: [f64; 3],
+        #[serde(default = "white")]
+        high: [f64; 3],
+    },
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum NoisePatternDescription {
+    Perlin,
+    Turbulence,
+    Fbm,
+    #[default]
+    Marble,
+    Wood,
+    Worley,
+}
+
+fn white() -> [f64; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+// A single number stands for a grey, which is how the scalar parameters of the principled
+// material are usually given
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColorSource {
+    Gray(f64),
+    Color([f64; 3]),
+    Texture(String),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDescription {
+    Lambertian {
+        albedo: ColorSource,
+    },
+    Metal {
+        albedo: ColorSource,
+        fuzz: f64,
+    },
+    Conductor {
+        metal: MetalDescription,
+        roughness: f64,
+    },
+    // Absorption is per unit of distance travelled inside, so thick parts come out darker
+    Dielectric {
+        refraction_index: RefractionDescription,
+        #[serde(default)]
+        roughness: f64,
+        #[serde(default)]
+        absorption: [f64; 3],
+    },
+    DiffuseLight {
+        emit: ColorSource,
+    },
+    // Parameters left out keep the values of Principled::new
+    Principled {
+        base_color: ColorSource,
+        metallic: Option<ColorSource>,
+        roughness: Option<ColorSource>,
+        specular: Option<ColorSource>,
+        clearcoat: Option<ColorSource>,
+        sheen: Option<ColorSource>,
+        transmission: Option<ColorSource>,
+        ior: Option<f64>,
+    },
+    // Two other materials by name, `weight` saying how much of the second shows
+    Mix {
+        first: String,
+        second: String,
+        weight: ColorSource,
+    },
+    // `coat` over `base`, showing as much as a dielectric of refractive index `ior` reflects
+    FresnelBlend {
+        base: String,
+        coat: String,
+        ior: f64,
+    },
+}
+
+// A fixed refractive index, one of the named glasses, or the coefficients of a dispersion model
+// for wavelengths in micrometres: { a, b } for Cauchy's equation or { b = [..], c = [..] } for
+// Sellmeier's
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RefractionDescription {
+    Index(f64),
+    Preset(GlassPreset),
+    Cauchy { a: f64, b: f64 },
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum GlassPreset {
+    Bk7,
+    Sf11,
+    Diamond,
+}
+
+// One of the named metals, or a complex index of refraction given per channel
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MetalDescription {
+    Preset(MetalPreset),
+    Ior { eta: [f64; 3], k: [f64; 3] },
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum MetalPreset {
+    Gold,
+    Copper,
+    Aluminium,
+    Silver,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ObjectDescription {
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+        material: String,
+    },
+    Triangle {
+        vertices: [[f64; 3]; 3],
+        material: String,
+    },
+    Quad {
+        q: [f64; 3],
+        u: [f64; 3],
+        v: [f64; 3],
+        material: String,
+    },
+    // Axis aligned box between two opposite corners
+    Box {
+        min: [f64; 3],
+        max: [f64; 3],
+        material: String,
+    },
+    // Materials come from the OBJ's own MTL libraries
+    Mesh {
+        path: PathBuf,
+    },
+    // Another object scaled, then rotated about x, y and z in degrees, then translated
+    Instance {
+        object: Box<ObjectDescription>,
+        translate: Option<[f64; 3]>,
+        rotate: Option<[f64; 3]>,
+        scale: Option<[f64; 3]>,
+    },
+}
+
+fn vec3(v: [f64; 3]) -> Vec3 {
+    Vec3::new(v[0], v[1], v[2])
+}
+
+impl SceneDescription {
+    fn build(self, path: &Path) -> Result<Scene, SceneError> {
+        let base_dir = path.parent().unwrap_or(Path::new(""));
+        let camera = self.camera.build(path)?;
+        let textures = self
+            .textures
+            .into_iter()
+            .map(|(name, texture)| Ok((name, texture.build(base_dir)?)))
+            .collect::<Result<HashMap<_, _>, SceneError>>()?;
+        let MaterialBuilder {
+            built: materials,
+            emissive,
+            ..
+        } = MaterialBuilder::new(self.materials, &textures, path).build_all()?;
+        let lookup = |object: usize, name: &str| {
+            materials
+                .get(name)
+                .cloned()
+                .ok_or_else(|| SceneError::UnknownMaterial {
+                    path: path.to_path_buf(),
+                    object,
+                    name: name.to_string(),
+                })
+        };
+
+        let mut world = HittableList::new();
+        let mut lights = HittableList::new();
+        for (index, object) in self.objects.into_iter().enumerate() {
+            let (object, material) = object.build(index, &lookup, base_dir, path)?;
+            if material.is_some_and(|name| emissive.contains(&name)) {
+                lights.add(object.clone());
+            }
+            world.add(object);
+        }
+
+        Ok(Scene {
+            world,
+            lights,
+            camera,
+        })
+    }
+}
+
+impl ObjectDescription {
+    // The object and the name of its material, None for meshes, which bring their own
+    fn build(
+        self,
+        index: usize,
+        lookup: &impl Fn(usize, &str) -> Result<Arc<dyn Material>, SceneError>,
+        base_dir: &Path,
+        path: &Path,
+    ) -> Result<(Arc<dyn Hittable>, Option<String>), SceneError> {
+        Ok(match self {
+            ObjectDescription::Sphere {
+                center,
+                radius,
+                material,
+            } => (
+                Arc::new(Sphere::new(vec3(center), radius, lookup(index, &material)?)),
+                Some(material),
+            ),
+            ObjectDescription::Triangle { vertices, material } => (
+                Arc::new(Triangle::new(
+                    vec3(vertices[0]),
+                    vec3(vertices[1]),
+                    vec3(vertices[2]),
+                    lookup(index, &material)?,
+                )),
+                Some(material),
+            ),
+            ObjectDescription::Quad { q, u, v, material } => (
+                Arc::new(Quad::new(
+                    vec3(q),
+                    vec3(u),
+                    vec3(v),
+                    lookup(index, &material)?,
+                )),
+                Some(material),
+            ),
+            ObjectDescription::Box { min, max, material } => (
+                Arc::new(make_box(&vec3(min), &vec3(max), lookup(index, &material)?)),
+                Some(material),
+            ),
+            ObjectDescription::Mesh { path } => (Arc::new(load_obj(base_dir.join(path))?), None),
+            ObjectDescription::Instance {
+                object,
+                translate,
+                rotate,
+                scale,
+            } => {
+                let (object, material) = object.build(index, lookup, base_dir, path)?;
+                let [x, y, z] = rotate.unwrap_or([0.0; 3]);
+                let matrix = Mat4::translation(&vec3(translate.unwrap_or([0.0; 3])))
+                    * Mat4::rotation_z(z)
+                    * Mat4::rotation_y(y)
+                    * Mat4::rotation_x(x)
+                    * Mat4::scaling(&vec3(scale.unwrap_or([1.0; 3])));
+                let transform =
+                    Transform::new(object, matrix).map_err(|source| SceneError::Transform {
+                        path: path.to_path_buf(),
+                        object: index,
+                        source,
+                    })?;
+                (Arc::new(transform), material)
+            }
+        })
+    }
+}
+
+impl CameraDescription {
+    fn build(self, path: &Path) -> Result<Camera, SceneError> {
+        let mut camera = Camera::new();
+        if let Some(aspect_ratio) = self.aspect_ratio {
+            camera.aspect_ratio = aspect_ratio;
+        }
+        if let Some(image_width) = self.image_width {
+            camera.image_width = image_width;
+        }
+        if let Some(samples_per_pixel) = self.samples_per_pixel {
+            camera.samples_per_pixel = samples_per_pixel;
+        }
+        if let Some(min_samples_per_pixel) = self.min_samples_per_pixel {
+            camera.min_samples_per_pixel = min_samples_per_pixel;
+        }
+        if let Some(adaptive_threshold) = self.adaptive_threshold {
+            camera.adaptive_threshold = adaptive_threshold;
+        }
+        if let Some(sampler) = self.sampler {
+            camera.sampler = match sampler {
+                SamplerDescription::Independent => SamplerKind::Independent,
+                SamplerDescription::Stratified => SamplerKind::Stratified,
+                SamplerDescription::Halton => SamplerKind::Halton,
+                SamplerDescription::Sobol => SamplerKind::Sobol,
+            };
+        }
+        if let Some(filter) = self.filter {
+            camera.filter = filter.build();
+        }
+        if let Some(spectral) = self.spectral {
+            camera.spectral = spectral;
+        }
+        if let Some(max_depth) = self.max_depth {
+            camera.max_depth = max_depth;
+        }
+        if let Some(vfov) = self.vfov {
+            camera.vfov = vfov;
+        }
+        if let Some(look_from) = self.look_from {
+            camera.look_from = vec3(look_from);
+        }
+        if let Some(look_at) = self.look_at {
+            camera.look_at = vec3(look_at);
+        }
+        if let Some(vup) = self.vup {
+            camera.vup = vec3(vup);
+        }
+        if let Some(defocus_angle) = self.defocus_angle {
+            camera.defocus_angle = defocus_angle;
+        }
+        if let Some(focus_dist) = self.focus_dist {
+            camera.focus_dist = focus_dist;
+        }
+        if let Some(seed) = self.seed {
+            camera.seed = seed;
+        }
+        if let Some(background) = self.background {
+            camera.background = match background {
+                BackgroundDescription::Solid { color } => Background::Solid(vec3(color)),
+                BackgroundDescription::Gradient { bottom, top } => Background::Gradient {
+                    bottom: vec3(bottom),
+                    top: vec3(top),
+                },
+            };
+        }
+
+        // Values the renderer can't make an image from
+        let invalid = |field, expected| SceneError::InvalidCamera {
+            path: path.to_path_buf(),
+            field,
+            expected,
+        };
+        let positive = |value: f64| value > 0.0 && value.is_finite();
+        if !positive(camera.aspect_ratio) {
+            return Err(invalid("aspect_ratio", "a positive number"));
+        }
+        if camera.image_width == 0 {
+            return Err(invalid("image_width", "at least 1"));
+        }
+        if camera.samples_per_pixel == 0 {
+            return Err(invalid("samples_per_pixel", "at least 1"));
+        }
+        if !positive(camera.filter.radius()) {
+            return Err(invalid("filter radius", "a positive number"));
+        }
+        Ok(camera)
+    }
+}
+
+impl FilterDescription {
+    fn build(self) -> Filter {
+        let or_default = |radius: Option<f64>, default: Filter| radius.unwrap_or(default.radius());
+        match self {
+            FilterDescription::Box { radius } => Filter::Box {
+                radius: or_default(radius, Filter::BOX),
+            },
+            FilterDescription::Tent { radius } => Filter::Tent {
+                radius: or_default(radius, Filter::TENT),
+            },
+            FilterDescription::Gaussian { radius, sigma } => {
+                let Filter::Gaussian { sigma: default, .. } = Filter::GAUSSIAN else {
+                    unreachable!()
+                };
+                Filter::Gaussian {
+                    radius: or_default(radius, Filter::GAUSSIAN),
+                    sigma: sigma.unwrap_or(default),
+                }
+            }
+            FilterDescription::Mitchell { radius, b, c } => {
+                let Filter::Mitchell {
+                    b: default_b,
+                    c: default_c,
+                    ..
+                } = Filter::MITCHELL
+                else {
+                    unreachable!()
+                };
+                Filter::Mitchell {
+                    radius: or_default(radius, Filter::MITCHELL),
+                    b: b.unwrap_or(default_b),
+                    c: c.unwrap_or(default_c),
+                }
+            }
+            FilterDescription::Lanczos { radius } => Filter::Lanczos {
+                radius: or_default(radius, Filter::LANCZOS),
+            },
+        }
+    }
+}
+
+impl RefractionDescription {
+    fn build(self, roughness: f64, absorption: Color) -> Dielectric {
+        let dispersion = match self {
+            RefractionDescription::Index(index) => {
+                return Dielectric::with_absorption(index, roughness, absorption)
+            }
+            RefractionDescription::Preset(GlassPreset::Bk7) => Dispersion::BK7,
+            RefractionDescription::Preset(GlassPreset::Sf11) => Dispersion::SF11,
+            RefractionDescription::Preset(GlassPreset::Diamond) => Dispersion::DIAMOND,
+            RefractionDescription::Cauchy { a, b } => Dispersion::Cauchy { a, b },
+            RefractionDescription::Sellmeier { b, c } => Dispersion::Sellmeier { b, c },
+        };
+        Dielectric::dispersive(dispersion, roughness, absorption)
+    }
+}
+
+impl TextureDescription {
+    fn build(self, base_dir: &Path) -> Result<Arc<dyn Texture>, SceneError> {
+        Ok(match self {
+            TextureDescription::Solid { color } => Arc::new(SolidColor::new(vec3(color))),
+            TextureDescription::Checker { scale, even, odd } => {
+                Arc::new(CheckerTexture::from_colors(scale, vec3(even), vec3(odd)))
+            }
+            TextureDescription::Image { path } => {
+                let path = base_dir.join(path);
+                let texture =
+                    ImageTexture::load(&path).map_err(|source| SceneError::Io { path, source })?;
+                Arc::new(texture)
+            }
+            TextureDescription::Noise {
+                pattern,
+                scale,
+                seed,
+                low,
+                high,
+            } => {
+                let pattern = match pattern {
+                    NoisePatternDescription::Perlin => NoisePattern::Perlin,
+                    NoisePatternDescription::Turbulence => NoisePattern::Turbulence,
+                    NoisePatternDescription::Fbm => NoisePattern::Fbm,
+                    NoisePatternDescription::Marble => NoisePattern::Marble,
+                    NoisePatternDescription::Wood => NoisePattern::Wood,
+                    NoisePatternDescription::Worley => NoisePattern::Worley,
+                };
+                Arc::new(NoiseTexture::with_colors(
+                    pattern,
+                    scale,
+                    seed,
+                    vec3(low),
+                    vec3(high),
+                ))
+            }
+        })
+    }
+}
+
+impl ColorSource {
+    fn build(
+        self,
+        textures: &HashMap<String, Arc<dyn Texture>>,
+        path: &Path,
+        material: &str,
+    ) -> Result<Arc<dyn Texture>, SceneError> {
+        match self {
+            ColorSource::Gray(value) => {
+                Ok(Arc::new(SolidColor::new(Color::new(value, value, value))))
+            }
+            ColorSource::Color(color) => Ok(Arc::new(SolidColor::new(vec3(color)))),
+            ColorSource::Texture(name) => {
+                textures
+                    .get(&name)
+                    .cloned()
+                    .ok_or_else(|| SceneError::UnknownTexture {
+                        path: path.to_path_buf(),
+                        material: material.to_string(),
+                        name,
+                    })
+            }
+        }
+    }
+}
+
+// Builds materials as they are first needed, so a mix can name materials declared anywhere in
+// the file
+struct MaterialBuilder<'a> {
+    descriptions: HashMap<String, MaterialDescription>,
+    declared: HashSet<String>,
+    textures: &'a HashMap<String, Arc<dyn Texture>>,
+    path: &'a Path,
+    built: HashMap<String, Arc<dyn Material>>,
+    // Materials that give off light, mixes containing a light included
+    emissive: HashSet<String>,
+}
+
+impl<'a> MaterialBuilder<'a> {
+    fn new(
+        descriptions: HashMap<String, MaterialDescription>,
+        textures: &'a HashMap<String, Arc<dyn Texture>>,
+        path: &'a Path,
+    ) -> MaterialBuilder<'a> {
+        MaterialBuilder {
+            declared: descriptions.keys().cloned().collect(),
+            descriptions,
+            textures,
+            path,
+            built: HashMap::new(),
+            emissive: HashSet::new(),
+        }
+    }
+
+    fn build_all(mut self) -> Result<MaterialBuilder<'a>, SceneError> {
+        let names: Vec<String> = self.descriptions.keys().cloned().collect();
+        for name in names {
+            if !self.built.contains_key(&name) {
+                self.build(&name)?;
+            }
+        }
+        Ok(self)
+    }
+
+    // Material `name` as used by the mix `material`
+    fn component(&mut self, name: &str, material: &str) -> Result<Arc<dyn Material>, SceneError> {
+        if let Some(built) = self.built.get(name) {
+            return Ok(built.clone());
+        }
+        if self.descriptions.contains_key(name) {
+            return self.build(name);
+        }
+        // Declared but neither built nor waiting to be, so it is being built further up and has
+        // been mixed into itself
+        if self.declared.contains(name) {
+            return Err(SceneError::MaterialCycle {
+                path: self.path.to_path_buf(),
+                material: name.to_string(),
+            });
+        }
+        Err(SceneError::UnknownComponent {
+            path: self.path.to_path_buf(),
+            material: material.to_string(),
+            name: name.to_string(),
+        })
+    }
+
+    fn build(&mut self, name: &str) -> Result<Arc<dyn Material>, SceneError> {
+        let description = self
+            .descriptions
+            .remove(name)
+            .expect("only declared materials are built");
+        let (light, components) = match &description {
+            MaterialDescription::DiffuseLight { .. } => (true, vec![]),
+            MaterialDescription::Mix { first, second, .. } => {
+                (false, vec![first.clone(), second.clone()])
+            }
+            MaterialDescription::FresnelBlend { base, coat, .. } => {
+                (false, vec![base.clone(), coat.clone()])
+            }
+            _ => (false, vec![]),
+        };
+
+        let material = description.build(self, name)?;
+        if light || components.iter().any(|name| self.emissive.contains(name)) {
+            self.emissive.insert(name.to_string());
+        }
+        self.built.insert(name.to_string(), material.clone());
+        Ok(material)
+    }
+}
+
+impl MaterialDescription {
+    fn build(
+        self,
+        materials: &mut MaterialBuilder,
+        name: &str,
+    ) -> Result<Arc<dyn Material>, SceneError> {
+        let (textures, path) = (materials.textures, materials.path);
+        Ok(match self {
+            MaterialDescription::Lambertian { albedo } => Arc::new(Lambertian::from_texture(
+                albedo.build(textures, path, name)?,
+            )),
+            MaterialDescription::Metal { albedo, fuzz } => Arc::new(Metal::from_texture(
+                albedo.build(textures, path, name)?,
+                fuzz,
+            )),
+            MaterialDescription::Conductor { metal, roughness } => Arc::new(match metal {
+                MetalDescription::Preset(preset) => {
+                    let preset = match preset {
+                        MetalPreset::Gold => ConductorPreset::Gold,
+                        MetalPreset::Copper => ConductorPreset::Copper,
+                        MetalPreset::Aluminium => ConductorPreset::Aluminium,
+                        MetalPreset::Silver => ConductorPreset::Silver,
+                    };
+                    Conductor::from_preset(preset, roughness)
+                }
+                MetalDescription::Ior { eta, k } => Conductor::new(vec3(eta), vec3(k), roughness),
+            }),
+            MaterialDescription::Dielectric {
+                refraction_index,
+                roughness,
+                absorption,
+            } => Arc::new(refraction_index.build(roughness, vec3(absorption))),
+            MaterialDescription::DiffuseLight { emit } => Arc::new(DiffuseLight::from_texture(
+                emit.build(textures, path, name)?,
+            )),
+            MaterialDescription::Principled {
+                base_color,
+                metallic,
+                roughness,
+                specular,
+                clearcoat,
+                sheen,
+                transmission,
+                ior,
+            } => {
+                let mut material = Principled::new(Color::new(0.0, 0.0, 0.0));
+                material.base_color = base_color.build(textures, path, name)?;
+                let parameters = [
+                    (metallic, &mut material.metallic),
+                    (roughness, &mut material.roughness),
+                    (specular, &mut material.specular),
+                    (clearcoat, &mut material.clearcoat),
+                    (sheen, &mut material.sheen),
+                    (transmission, &mut material.transmission),
+                ];
+                for (source, texture) in parameters {
+                    if let Some(source) = source {
+                        *texture = source.build(textures, path, name)?;
+                    }
+                }
+                if let Some(ior) = ior {
+                    material.ior = ior;
+                }
+                Arc::new(material)
+            }
+            MaterialDescription::Mix {
+                first,
+                second,
+                weight,
+            } => Arc::new(MixMaterial::from_texture(
+                materials.component(&first, name)?,
+                materials.component(&second, name)?,
+                weight.build(textures, path, name)?,
+            )),
+            MaterialDescription::FresnelBlend { base, coat, ior } => {
+                Arc::new(MixMaterial::fresnel(
+                    materials.component(&base, name)?,
+                    materials.component(&coat, name)?,
+                    ior,
+                ))
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<Scene, SceneError> {
+        parse_scene(text, Path::new("test.toml"))
+    }
+
+    #[test]
+    fn camera_values_are_loaded() {
+        let scene = parse(
+            "[camera]\n\
+             aspect_ratio = 2.0\n\
+             image_width = 64\n\
+             samples_per_pixel = 3\n\
+             filter = { type = \"tent\", radius = 1.5 }\n",
+        )
+        .unwrap();
+        assert_eq!(scene.camera.aspect_ratio, 2.0);
+        assert_eq!(scene.camera.image_width, 64);
+        assert_eq!(scene.camera.samples_per_pixel, 3);
+        assert_eq!(scene.camera.filter, Filter::Tent { radius: 1.5 });
+    }
+
+    #[test]
+    fn invalid_camera_values_are_rejected() {
+        let cases = [
+            ("aspect_ratio = 0.0", "aspect_ratio"),
+            ("aspect_ratio = -1.5", "aspect_ratio"),
+            ("aspect_ratio = inf", "aspect_ratio"),
+            ("image_width = 0", "image_width"),
+            ("samples_per_pixel = 0", "samples_per_pixel"),
+            ("filter = { type = \"box\", radius = 0.0 }", "filter radius"),
+            (
+                "filter = { type = \"mitchell\", radius = -2.0 }",
+                "filter radius",
+            ),
+        ];
+        for (line, expected_field) in cases {
+            match parse(&format!("[camera]\n{}\n", line)) {
+                Err(SceneError::InvalidCamera { field, .. }) => {
+                    assert_eq!(field, expected_field, "{}", line)
+                }
+                Err(error) => panic!("{}: unexpected error {}", line, error),
+                Ok(_) => panic!("{}: accepted", line),
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_camera_error_names_the_field() {
+        let Err(error) = parse("[camera]\nimage_width = 0\n") else {
+            panic!("accepted a zero image width");
+        };
+        assert_eq!(
+            error.to_string(),
+            "test.toml: camera image_width must be at least 1"
+        );
+    }
+}
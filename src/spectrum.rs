@@ -0,0 +1,118 @@
+use std::sync::OnceLock;
+
+use crate::color::Color;
+use crate::vector::Vec3;
+
+// Range of wavelengths in nanometres that spectral renders trace
+pub const LAMBDA_MIN: f64 = 360.0;
+pub const LAMBDA_MAX: f64 = 830.0;
+
+// Pick a wavelength from a number in [0, 1), returning it with its density. Wavelengths the eye
+// is most sensitive to come up most often (Radziszewski et al. 2009).
+pub fn sample_wavelength(u: f64) -> (f64, f64) {
+    let lambda = 538.0 - 138.888889 * (0.85691062 - 1.82750197 * u).atanh();
+    let lambda = lambda.clamp(LAMBDA_MIN, LAMBDA_MAX);
+    (lambda, wavelength_pdf(lambda))
+}
+
+pub fn wavelength_pdf(lambda: f64) -> f64 {
+    if !(LAMBDA_MIN..=LAMBDA_MAX).contains(&lambda) {
+        return 0.0;
+    }
+    0.0039398042 / (0.0072 * (lambda - 538.0)).cosh().powi(2)
+}
+
+// CIE 1931 colour matching functions, using the multi-lobe fit of Wyman, Sloan and Shirley
+pub fn cie_xyz(lambda: f64) -> Vec3 {
+    // Gaussian with a different width either side of its peak
+    let g = |mu: f64, sigma_below: f64, sigma_above: f64| {
+        let sigma = if lambda < mu {
+            sigma_below
+        } else {
+            sigma_above
+        };
+        let t = (lambda - mu) / sigma;
+        (-0.5 * t * t).exp()
+    };
+    Vec3::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+// CIE XYZ to linear sRGB with a D65 white point
+pub fn xyz_to_rgb(xyz: &Vec3) -> Color {
+    Color::new(
+        3.2404542 * xyz.x() - 1.5371385 * xyz.y() - 0.4985314 * xyz.z(),
+        -0.9692660 * xyz.x() + 1.8760108 * xyz.y() + 0.0415560 * xyz.z(),
+        0.0556434 * xyz.x() - 0.2040259 * xyz.y() + 1.0572252 * xyz.z(),
+    )
+}
+
+// Linear sRGB response to a single wavelength, scaled so that light of equal energy at every
+// wavelength comes out white. Some channels go negative for saturated wavelengths, which is how
+// sRGB represents colours outside its gamut.
+pub fn wavelength_to_rgb(lambda: f64) -> Color {
+    let white = white_response();
+    let rgb = xyz_to_rgb(&cie_xyz(lambda));
+    Color::new(
+        rgb.x() / white.x(),
+        rgb.y() / white.y(),
+        rgb.z() / white.z(),
+    )
+}
+
+// Response of each channel to every wavelength at once, integrated in 1 nm steps
+fn white_response() -> Color {
+    static WHITE: OnceLock<Color> = OnceLock::new();
+    *WHITE.get_or_init(|| {
+        let steps = (LAMBDA_MAX - LAMBDA_MIN) as u32;
+        (0..=steps)
+            .map(|i| xyz_to_rgb(&cie_xyz(LAMBDA_MIN + i as f64)))
+            .fold(Color::new(0.0, 0.0, 0.0), |sum, rgb| sum + rgb)
+    })
+}
+
+// How the refractive index of a dielectric varies with wavelength. Both models take wavelengths
+// in micrometres, as their coefficients are usually published.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dispersion {
+    // n = a + b / wavelength^2
+    Cauchy { a: f64, b: f64 },
+    // n^2 = 1 + sum of b_i wavelength^2 / (wavelength^2 - c_i)
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    // Schott N-BK7, the common crown glass of lenses
+    pub const BK7: Dispersion = Dispersion::Sellmeier {
+        b: [1.03961212, 0.231792344, 1.01046945],
+        c: [0.00600069867, 0.0200179144, 103.560653],
+    };
+    // Schott N-SF11, a dense flint glass that splits colours strongly, as prisms do
+    pub const SF11: Dispersion = Dispersion::Sellmeier {
+        b: [1.73759695, 0.313747346, 1.89878101],
+        c: [0.013188707, 0.0623068142, 155.23629],
+    };
+    pub const DIAMOND: Dispersion = Dispersion::Sellmeier {
+        b: [4.3356, 0.3306, 0.0],
+        c: [0.011236, 0.030625, 0.0],
+    };
+
+    // Wavelength of the sodium d line, where single refractive indices are usually quoted
+    pub const D_LINE: f64 = 587.6;
+
+    // Refractive index at a wavelength in nanometres
+    pub fn ior(&self, lambda: f64) -> f64 {
+        let micrometres = lambda / 1000.0;
+        let l2 = micrometres * micrometres;
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let sum: f64 = b.iter().zip(c).map(|(b, c)| b * l2 / (l2 - c)).sum();
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
+}
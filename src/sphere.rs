@@ -1,29 +1,46 @@
+use std::sync::Arc;
+
 use crate::{
+    aabb::Aabb,
+    constants::{INFINITY, PI},
     hittable::{HitRecord, Hittable},
     interval::Interval,
     material::Material,
+    onb::Onb,
     ray::Ray,
-    vector::Point3,
+    utils::{random_f64, Rng},
+    vector::{Point3, Vec3},
 };
 
 pub struct Sphere {
     center: Point3,
     radius: f64,
-    pub material: Box<dyn Material>,
+    pub material: Arc<dyn Material>,
+    bbox: Aabb,
 }
 
 impl Sphere {
-    pub fn new(center: Point3, radius: f64, material: Box<dyn Material>) -> Sphere {
+    pub fn new(center: Point3, radius: f64, material: Arc<dyn Material>) -> Sphere {
+        let rvec = Vec3::new(radius, radius, radius);
+        let bbox = Aabb::from_points(&(center - rvec), &(center + rvec));
         Sphere {
             center,
             radius,
             material,
+            bbox,
         }
     }
+
+    // u runs around the y axis starting from -x, v from the bottom pole to the top
+    fn get_sphere_uv(p: &Point3) -> (f64, f64) {
+        let theta = (-p.y()).acos();
+        let phi = (-p.z()).atan2(p.x()) + PI;
+        (phi / (2.0 * PI), theta / PI)
+    }
 }
 
 impl Hittable for Sphere {
-    fn hit(&self, ray: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+    fn hit<'a>(&'a self, ray: &Ray, ray_t: Interval, rec: &mut HitRecord<'a>) -> bool {
         let oc = &self.center - ray.origin();
         let a = ray.direction().length_squared();
         let h = ray.direction().dot(&oc);
@@ -43,17 +60,53 @@ impl Hittable for Sphere {
 
         rec.t = root;
         rec.p = ray.at(rec.t);
-        let outward_normal = (&rec.p - &self.center) / self.radius;
+        let outward_normal = (rec.p - self.center) / self.radius;
         rec.set_face_normal(ray, &outward_normal);
-        rec.material = self.material.clone();
+        (rec.u, rec.v) = Sphere::get_sphere_uv(&outward_normal);
+        rec.material = self.material.as_ref();
         true
     }
 
-    fn clone_box(&self) -> Box<dyn Hittable> {
-        Box::new(Sphere {
-            center: self.center,
-            radius: self.radius,
-            material: self.material.clone_box(),
-        })
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+
+    // Sampling is uniform over the cone of directions the sphere covers as seen from origin
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let distance_squared = (self.center - *origin).length_squared();
+        if distance_squared <= self.radius * self.radius {
+            return 0.0;
+        }
+
+        let mut rec = HitRecord::default();
+        if !self.hit(
+            &Ray::new(*origin, *direction),
+            Interval::new(0.001, INFINITY),
+            &mut rec,
+        ) {
+            return 0.0;
+        }
+
+        let cos_theta_max = (1.0 - self.radius * self.radius / distance_squared).sqrt();
+        let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
+        1.0 / solid_angle
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut Rng) -> Vec3 {
+        let direction = self.center - *origin;
+        let distance_squared = direction.length_squared();
+        if distance_squared <= self.radius * self.radius {
+            return Vec3::random_unit_vector(rng);
+        }
+
+        let r1 = random_f64(rng);
+        let r2 = random_f64(rng);
+        let cos_theta_max = (1.0 - self.radius * self.radius / distance_squared).sqrt();
+        let z = 1.0 + r2 * (cos_theta_max - 1.0);
+        let phi = 2.0 * PI * r1;
+        let sin_theta = (1.0 - z * z).sqrt();
+
+        let uvw = Onb::new(&direction);
+        uvw.transform(&Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z))
     }
 }
@@ -0,0 +1,392 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::{
+    color::Color,
+    framebuffer::FrameBuffer,
+    interval::Interval,
+    noise::{Perlin, Worley},
+    utils::gamma_to_linear,
+    vector::Point3,
+};
+
+// Colour looked up from surface coordinates (u, v) and the hit point p
+pub trait Texture: Send + Sync {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color;
+}
+
+#[derive(Debug, Clone)]
+pub struct SolidColor {
+    albedo: Color,
+}
+
+impl SolidColor {
+    pub fn new(albedo: Color) -> SolidColor {
+        SolidColor { albedo }
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
+        self.albedo
+    }
+}
+
+// Alternating 3D cells of two textures, each `scale` units wide
+#[derive(Clone)]
+pub struct CheckerTexture {
+    inv_scale: f64,
+    even: Arc<dyn Texture>,
+    odd: Arc<dyn Texture>,
+}
+
+impl CheckerTexture {
+    pub fn new(scale: f64, even: Arc<dyn Texture>, odd: Arc<dyn Texture>) -> CheckerTexture {
+        CheckerTexture {
+            inv_scale: 1.0 / scale,
+            even,
+            odd,
+        }
+    }
+
+    pub fn from_colors(scale: f64, even: Color, odd: Color) -> CheckerTexture {
+        CheckerTexture::new(
+            scale,
+            Arc::new(SolidColor::new(even)),
+            Arc::new(SolidColor::new(odd)),
+        )
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        let x = (self.inv_scale * p.x()).floor() as i64;
+        let y = (self.inv_scale * p.y()).floor() as i64;
+        let z = (self.inv_scale * p.z()).floor() as i64;
+
+        if (x + y + z) % 2 == 0 {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+}
+
+// Image mapped over the (u, v) unit square, with v = 0 at the bottom of the image. The pixels
+// are shared between clones.
+#[derive(Debug, Clone)]
+pub struct ImageTexture {
+    image: Arc<FrameBuffer>,
+}
+
+impl ImageTexture {
+    pub fn new(image: FrameBuffer) -> ImageTexture {
+        ImageTexture {
+            image: Arc::new(image),
+        }
+    }
+
+    // Load a PNG or PPM (P3 or P6) file. 8 bit values are treated as gamma encoded, like the
+    // images we write.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<ImageTexture> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        let reader = BufReader::new(File::open(path)?);
+        let image = match extension.as_deref() {
+            Some("png") => read_png(reader)?,
+            Some("ppm") => read_ppm(reader)?,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unsupported texture format for {}", path.display()),
+                ))
+            }
+        };
+        Ok(ImageTexture::new(image))
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: &Point3) -> Color {
+        if self.image.width() == 0 || self.image.height() == 0 {
+            return Color::new(0.0, 1.0, 1.0);
+        }
+
+        let unit = Interval::new(0.0, 1.0);
+        let u = unit.clamp(u);
+        let v = 1.0 - unit.clamp(v); // Image rows go top to bottom
+
+        let i = ((u * self.image.width() as f64) as u32).min(self.image.width() - 1);
+        let j = ((v * self.image.height() as f64) as u32).min(self.image.height() - 1);
+        self.image.get_pixel(i, j)
+    }
+}
+
+// Octaves summed by the fractal patterns
+const NOISE_DEPTH: u32 = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoisePattern {
+    Perlin,
+    Turbulence,
+    Fbm,
+    // Veins of turbulence running along z
+    Marble,
+    // Noisy rings around the y axis
+    Wood,
+    // Distance to the nearest Worley feature point
+    Worley,
+}
+
+// Procedural solid texture blending from `low` to `high` as the pattern goes from 0 to 1.
+// `scale` is the pattern frequency.
+#[derive(Debug, Clone)]
+pub struct NoiseTexture {
+    pattern: NoisePattern,
+    scale: f64,
+    low: Color,
+    high: Color,
+    perlin: Arc<Perlin>,
+    worley: Arc<Worley>,
+}
+
+impl NoiseTexture {
+    pub fn new(pattern: NoisePattern, scale: f64, seed: u64) -> NoiseTexture {
+        let black = Color::new(0.0, 0.0, 0.0);
+        let white = Color::new(1.0, 1.0, 1.0);
+        NoiseTexture::with_colors(pattern, scale, seed, black, white)
+    }
+
+    pub fn with_colors(
+        pattern: NoisePattern,
+        scale: f64,
+        seed: u64,
+        low: Color,
+        high: Color,
+    ) -> NoiseTexture {
+        NoiseTexture {
+            pattern,
+            scale,
+            low,
+            high,
+            perlin: Arc::new(Perlin::new(seed)),
+            worley: Arc::new(Worley::new(seed)),
+        }
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        let scaled = p * self.scale;
+        let t = match self.pattern {
+            NoisePattern::Perlin => 0.5 * (1.0 + self.perlin.noise(&scaled)),
+            NoisePattern::Turbulence => self.perlin.turb(&scaled, NOISE_DEPTH),
+            NoisePattern::Fbm => 0.5 * (1.0 + self.perlin.fbm(&scaled, NOISE_DEPTH, 2.0, 0.5)),
+            NoisePattern::Marble => {
+                let phase = scaled.z() + 10.0 * self.perlin.turb(p, NOISE_DEPTH);
+                0.5 * (1.0 + phase.sin())
+            }
+            NoisePattern::Wood => {
+                let rings = scaled.x().hypot(scaled.z())
+                    + 0.5 * self.perlin.fbm(&scaled, NOISE_DEPTH, 2.0, 0.5);
+                rings - rings.floor()
+            }
+            NoisePattern::Worley => self.worley.noise(&scaled),
+        };
+        let t = Interval::new(0.0, 1.0).clamp(t);
+        self.low * (1.0 - t) + self.high * t
+    }
+}
+
+fn read_png<R: Read>(reader: R) -> io::Result<FrameBuffer> {
+    let mut decoder = png::Decoder::new(reader);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut png_reader = decoder.read_info()?;
+    let mut buffer = vec![0; png_reader.output_buffer_size()];
+    let info = png_reader.next_frame(&mut buffer)?;
+
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::Rgb => 3,
+        png::ColorType::Rgba => 4,
+        png::ColorType::Indexed => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "indexed png was not expanded",
+            ))
+        }
+    };
+
+    let mut image = FrameBuffer::new(info.width, info.height);
+    let pixels = buffer[..info.buffer_size()].chunks(channels);
+    for (pixel, bytes) in image.pixels_mut().iter_mut().zip(pixels) {
+        // Grey images repeat the one channel, alpha is dropped
+        let (r, g, b) = if channels < 3 {
+            (bytes[0], bytes[0], bytes[0])
+        } else {
+            (bytes[0], bytes[1], bytes[2])
+        };
+        *pixel = color_from_bytes(r, g, b, 255);
+    }
+    Ok(image)
+}
+
+fn read_ppm<R: Read>(mut reader: R) -> io::Result<FrameBuffer> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+    // The header is four whitespace separated tokens, with # comments allowed in between
+    let mut position = 0;
+    let mut header = vec![];
+    while header.len() < 4 {
+        while position < data.len() && data[position].is_ascii_whitespace() {
+            position += 1;
+        }
+        if position < data.len() && data[position] == b'#' {
+            while position < data.len() && data[position] != b'\n' {
+                position += 1;
+            }
+            continue;
+        }
+        let start = position;
+        while position < data.len() && !data[position].is_ascii_whitespace() {
+            position += 1;
+        }
+        if start == position {
+            return Err(invalid("truncated ppm header"));
+        }
+        header.push(String::from_utf8_lossy(&data[start..position]).into_owned());
+    }
+    // Exactly one whitespace byte separates the header from binary data
+    position += 1;
+
+    let parse = |token: &str| {
+        token
+            .parse::<u32>()
+            .map_err(|_| invalid("invalid number in ppm header"))
+    };
+    let (width, height, max_value) = (parse(&header[1])?, parse(&header[2])?, parse(&header[3])?);
+    if max_value == 0 || max_value > 255 {
+        return Err(invalid("only 8 bit ppm files are supported"));
+    }
+
+    let count = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|pixels| pixels.checked_mul(3))
+        .ok_or_else(|| invalid("ppm image too large"))?;
+    let bytes: Vec<u8> = match header[0].as_str() {
+        "P6" => position
+            .checked_add(count)
+            .and_then(|end| data.get(position..end))
+            .ok_or_else(|| invalid("truncated ppm data"))?
+            .to_vec(),
+        "P3" => {
+            let text = String::from_utf8_lossy(data.get(position..).unwrap_or(&[]));
+            let values = text
+                .split_ascii_whitespace()
+                .take(count)
+                .map(|token| token.parse::<u8>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| invalid("invalid ppm pixel value"))?;
+            if values.len() != count {
+                return Err(invalid("truncated ppm data"));
+            }
+            values
+        }
+        _ => return Err(invalid("not a P3 or P6 ppm file")),
+    };
+
+    let mut image = FrameBuffer::new(width, height);
+    for (pixel, rgb) in image.pixels_mut().iter_mut().zip(bytes.chunks(3)) {
+        *pixel = color_from_bytes(rgb[0], rgb[1], rgb[2], max_value);
+    }
+    Ok(image)
+}
+
+fn color_from_bytes(r: u8, g: u8, b: u8, max_value: u32) -> Color {
+    let max_value = max_value as f64;
+    Color::new(
+        gamma_to_linear(r as f64 / max_value),
+        gamma_to_linear(g as f64 / max_value),
+        gamma_to_linear(b as f64 / max_value),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(data: &[u8]) -> String {
+        read_ppm(data).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn ppm_header_comments_are_skipped() {
+        let data = b"P3\n# made by hand\n2 # width\n 1\n#max\n255\n255 0 0  0 255 255\n";
+        let image = read_ppm(&data[..]).unwrap();
+        assert_eq!((image.width(), image.height()), (2, 1));
+        assert_eq!(image.get_pixel(0, 0), Color::new(1.0, 0.0, 0.0));
+        assert_eq!(image.get_pixel(1, 0), Color::new(0.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn p3_and_p6_read_the_same_pixels() {
+        let bytes = [0u8, 51, 255, 255, 128, 0, 10, 20, 30, 200, 100, 50];
+        let text = bytes.map(|b| b.to_string()).join(" ");
+        let p3 = read_ppm(format!("P3 2 2 255\n{}\n", text).as_bytes()).unwrap();
+        let mut p6 = b"P6 2 2 255\n".to_vec();
+        p6.extend(bytes);
+        let p6 = read_ppm(&p6[..]).unwrap();
+        assert_eq!(p3.pixels(), p6.pixels());
+        assert_eq!(p3.get_pixel(1, 1), color_from_bytes(200, 100, 50, 255));
+    }
+
+    #[test]
+    fn p6_data_may_contain_whitespace_bytes() {
+        // A newline as the first pixel byte must not be taken for header whitespace
+        let image = read_ppm(&b"P6\n1 1\n255\n\n\x20\x09"[..]).unwrap();
+        assert_eq!(image.get_pixel(0, 0), color_from_bytes(10, 32, 9, 255));
+    }
+
+    #[test]
+    fn small_max_values_are_scaled() {
+        let image = read_ppm(&b"P3 2 1 15 15 15 15 0 0 0"[..]).unwrap();
+        assert_eq!(image.get_pixel(0, 0), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(image.get_pixel(1, 0), Color::default());
+    }
+
+    #[test]
+    fn wide_max_values_are_rejected() {
+        let message = "only 8 bit ppm files are supported";
+        assert_eq!(error(b"P6 1 1 65535\n\0\0\0\0\0\0"), message);
+        assert_eq!(error(b"P3 1 1 256 0 0 0"), message);
+    }
+
+    #[test]
+    fn malformed_ppm_files_are_rejected() {
+        assert_eq!(error(b"P5 1 1 255\n\0"), "not a P3 or P6 ppm file");
+        assert_eq!(error(b"P6 1 1"), "truncated ppm header");
+        assert_eq!(error(b"P6 1 -1 255\n"), "invalid number in ppm header");
+        assert_eq!(error(b"P3 1 1 0 0 0 0"), "only 8 bit ppm files are supported");
+        assert_eq!(error(b"P6 2 2 255\n\0\0\0"), "truncated ppm data");
+        assert_eq!(error(b"P3 1 1 255 0 0"), "truncated ppm data");
+        assert_eq!(error(b"P3 1 1 255 0 0 300"), "invalid ppm pixel value");
+    }
+
+    #[test]
+    fn oversized_ppm_headers_are_rejected_without_allocating() {
+        assert_eq!(
+            error(b"P6 4294967295 4294967295 255\n"),
+            "ppm image too large"
+        );
+        assert_eq!(error(b"P6 100000 100000 255\n\0\0\0"), "truncated ppm data");
+        assert_eq!(error(b"P3 100000 100000 255 0 0 0"), "truncated ppm data");
+    }
+}
@@ -0,0 +1,367 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::{
+    aabb::Aabb,
+    hittable::{HitRecord, Hittable},
+    interval::Interval,
+    matrix::Mat4,
+    ray::Ray,
+    utils::Rng,
+    vector::{Point3, Vec3},
+};
+
+// An object placed in the world by an affine matrix. The object itself is shared, so any
+// number of instances can reuse one copy of its geometry.
+#[derive(Clone)]
+pub struct Transform {
+    object: Arc<dyn Hittable>,
+    object_to_world: Mat4,
+    world_to_object: Mat4,
+    // Normals transform by the inverse transpose to stay perpendicular to the surface
+    normal_to_world: Mat4,
+    // Absolute determinant of the linear part, the factor volumes are scaled by
+    determinant: f64,
+    bbox: Aabb,
+}
+
+// A matrix that flattens space, such as a scale of 0 along some axis, so rays can't be taken
+// back into the object's space
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SingularMatrix;
+
+impl fmt::Display for SingularMatrix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "transform matrix is not invertible")
+    }
+}
+
+impl std::error::Error for SingularMatrix {}
+
+impl Transform {
+    pub fn new(
+        object: Arc<dyn Hittable>,
+        object_to_world: Mat4,
+    ) -> Result<Transform, SingularMatrix> {
+        let world_to_object = object_to_world.inverse().ok_or(SingularMatrix)?;
+        Ok(Transform::with_inverse(
+            object,
+            object_to_world,
+            world_to_object,
+        ))
+    }
+
+    // For matrices whose inverse is known without solving for it
+    fn with_inverse(
+        object: Arc<dyn Hittable>,
+        object_to_world: Mat4,
+        world_to_object: Mat4,
+    ) -> Transform {
+        let x = object_to_world.transform_vector(&Vec3::new(1.0, 0.0, 0.0));
+        let y = object_to_world.transform_vector(&Vec3::new(0.0, 1.0, 0.0));
+        let z = object_to_world.transform_vector(&Vec3::new(0.0, 0.0, 1.0));
+        let determinant = x.dot(&y.cross(&z)).abs();
+
+        let bbox = Transform::transform_box(&object.bounding_box(), &object_to_world);
+        Transform {
+            object,
+            object_to_world,
+            world_to_object,
+            normal_to_world: world_to_object.transpose(),
+            determinant,
+            bbox,
+        }
+    }
+
+    // The transform applied after this one's, without nesting another wrapper
+    pub fn then(&self, matrix: Mat4) -> Result<Transform, SingularMatrix> {
+        Transform::new(self.object.clone(), matrix * self.object_to_world)
+    }
+
+    pub fn matrix(&self) -> Mat4 {
+        self.object_to_world
+    }
+
+    // Box around the eight transformed corners
+    fn transform_box(bbox: &Aabb, matrix: &Mat4) -> Aabb {
+        if bbox.x.size() < 0.0 || bbox.y.size() < 0.0 || bbox.z.size() < 0.0 {
+            return Aabb::EMPTY;
+        }
+
+        let mut min = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for i in 0..8 {
+            let corner = Point3::new(
+                if i & 1 == 0 { bbox.x.min } else { bbox.x.max },
+                if i & 2 == 0 { bbox.y.min } else { bbox.y.max },
+                if i & 4 == 0 { bbox.z.min } else { bbox.z.max },
+            );
+            let p = matrix.transform_point(&corner);
+            min = Point3::new(min.x().min(p.x()), min.y().min(p.y()), min.z().min(p.z()));
+            max = Point3::new(max.x().max(p.x()), max.y().max(p.y()), max.z().max(p.z()));
+        }
+        Aabb::from_points(&min, &max)
+    }
+}
+
+impl Hittable for Transform {
+    fn hit<'a>(&'a self, ray: &Ray, ray_t: Interval, rec: &mut HitRecord<'a>) -> bool {
+        // The direction isn't normalised so t means the same thing in both spaces
+        let object_ray = Ray::new(
+            self.world_to_object.transform_point(ray.origin()),
+            self.world_to_object.transform_vector(ray.direction()),
+        )
+        .with_wavelength(ray.wavelength());
+        if !self.object.hit(&object_ray, ray_t, rec) {
+            return false;
+        }
+
+        // The object already faced its normal against the ray, and the inverse transpose keeps
+        // the sign of that dot product
+        rec.p = self.object_to_world.transform_point(&rec.p);
+        rec.normal = self
+            .normal_to_world
+            .transform_vector(&rec.normal)
+            .normalize();
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let object_origin = self.world_to_object.transform_point(origin);
+        let object_direction = self.world_to_object.transform_vector(direction).normalize();
+        let pdf = self.object.pdf_value(&object_origin, &object_direction);
+
+        // Change of variables between solid angles: a direction d maps to M d, and a small cone
+        // around it grows by det(M) / |M d|^3
+        let stretch = self
+            .object_to_world
+            .transform_vector(&object_direction)
+            .length();
+        pdf * stretch * stretch * stretch / self.determinant
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut Rng) -> Vec3 {
+        let object_origin = self.world_to_object.transform_point(origin);
+        let direction = self.object.random(&object_origin, rng);
+        self.object_to_world.transform_vector(&direction)
+    }
+}
+
+#[derive(Clone)]
+pub struct Translate {
+    transform: Transform,
+}
+
+impl Translate {
+    pub fn new(object: Arc<dyn Hittable>, offset: Vec3) -> Translate {
+        Translate {
+            transform: Transform::with_inverse(
+                object,
+                Mat4::translation(&offset),
+                Mat4::translation(&(offset * -1.0)),
+            ),
+        }
+    }
+}
+
+// Rotations take degrees and turn about the world axis through the object's origin
+#[derive(Clone)]
+pub struct RotateX {
+    transform: Transform,
+}
+
+impl RotateX {
+    pub fn new(object: Arc<dyn Hittable>, angle: f64) -> RotateX {
+        RotateX {
+            transform: Transform::with_inverse(
+                object,
+                Mat4::rotation_x(angle),
+                Mat4::rotation_x(-angle),
+            ),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RotateY {
+    transform: Transform,
+}
+
+impl RotateY {
+    pub fn new(object: Arc<dyn Hittable>, angle: f64) -> RotateY {
+        RotateY {
+            transform: Transform::with_inverse(
+                object,
+                Mat4::rotation_y(angle),
+                Mat4::rotation_y(-angle),
+            ),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RotateZ {
+    transform: Transform,
+}
+
+impl RotateZ {
+    pub fn new(object: Arc<dyn Hittable>, angle: f64) -> RotateZ {
+        RotateZ {
+            transform: Transform::with_inverse(
+                object,
+                Mat4::rotation_z(angle),
+                Mat4::rotation_z(-angle),
+            ),
+        }
+    }
+}
+
+// Scales about the object's origin, each axis by its own factor
+#[derive(Clone)]
+pub struct Scale {
+    transform: Transform,
+}
+
+impl Scale {
+    pub fn new(object: Arc<dyn Hittable>, factors: Vec3) -> Result<Scale, SingularMatrix> {
+        Ok(Scale {
+            transform: Transform::new(object, Mat4::scaling(&factors))?,
+        })
+    }
+}
+
+// The named wrappers are plain transforms underneath
+macro_rules! hittable_via_transform {
+    ($($wrapper:ty),*) => {
+        $(
+            impl Hittable for $wrapper {
+                fn hit<'a>(&'a self, ray: &Ray, ray_t: Interval, rec: &mut HitRecord<'a>) -> bool {
+                    self.transform.hit(ray, ray_t, rec)
+                }
+
+                fn bounding_box(&self) -> Aabb {
+                    self.transform.bounding_box()
+                }
+
+                fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+                    self.transform.pdf_value(origin, direction)
+                }
+
+                fn random(&self, origin: &Point3, rng: &mut Rng) -> Vec3 {
+                    self.transform.random(origin, rng)
+                }
+            }
+
+            impl From<$wrapper> for Transform {
+                fn from(wrapper: $wrapper) -> Transform {
+                    wrapper.transform
+                }
+            }
+        )*
+    };
+}
+
+hittable_via_transform!(Translate, RotateX, RotateY, RotateZ, Scale);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::PI;
+    use crate::material::DiffuseLight;
+    use crate::sphere::Sphere;
+    use crate::utils::seeded_rng;
+
+    fn light(center: Point3, radius: f64) -> Arc<dyn Hittable> {
+        let material = Arc::new(DiffuseLight::new(Vec3::new(1.0, 1.0, 1.0)));
+        Arc::new(Sphere::new(center, radius, material))
+    }
+
+    // Integral of the pdf over all directions, by the midpoint rule in cos theta and phi. Also
+    // returns the solid angle the object covers.
+    fn integrate_pdf(object: &dyn Hittable, origin: Point3) -> (f64, f64) {
+        let n = 600;
+        let cell = (2.0 / n as f64) * (2.0 * PI / n as f64);
+        let (mut total, mut covered) = (0.0, 0.0);
+        for k in 0..n {
+            let z = -1.0 + (k as f64 + 0.5) * 2.0 / n as f64;
+            let r = (1.0 - z * z).sqrt();
+            for l in 0..n {
+                let phi = (l as f64 + 0.5) * 2.0 * PI / n as f64;
+                let direction = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+                let pdf = object.pdf_value(&origin, &direction);
+                total += pdf * cell;
+                if pdf > 0.0 {
+                    covered += cell;
+                }
+            }
+        }
+        (total, covered)
+    }
+
+    fn assert_pdf_normalized(object: &dyn Hittable, origin: Point3) {
+        let (total, solid_angle) = integrate_pdf(object, origin);
+        assert!((total - 1.0).abs() < 0.01, "pdf integrates to {}", total);
+
+        // Sampled directions hit the object, and weighting them by 1 / pdf recovers its size
+        let mut rng = seeded_rng(3, 0);
+        let samples = 20_000;
+        let mut estimate = 0.0;
+        for _ in 0..samples {
+            let direction = object.random(&origin, &mut rng);
+            let pdf = object.pdf_value(&origin, &direction);
+            assert!(pdf > 0.0, "sampled direction {:?} misses", direction);
+            estimate += 1.0 / pdf / samples as f64;
+        }
+        assert!(
+            (estimate / solid_angle - 1.0).abs() < 0.02,
+            "sampled solid angle {} but the object covers {}",
+            estimate,
+            solid_angle
+        );
+    }
+
+    #[test]
+    fn scaled_light_pdf_integrates_to_one() {
+        let ellipsoid = Scale::new(light(Point3::default(), 1.0), Vec3::new(2.0, 0.5, 1.0));
+        assert_pdf_normalized(&ellipsoid.unwrap(), Point3::new(0.5, 0.3, 3.0));
+        let shrunk = Scale::new(
+            light(Point3::new(1.0, 0.0, 0.0), 1.0),
+            Vec3::new(0.3, 0.3, 0.3),
+        );
+        assert_pdf_normalized(&shrunk.unwrap(), Point3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn rotated_light_pdf_integrates_to_one() {
+        // Off centre, so the rotation moves the sphere as well as turning it
+        let moved = RotateY::new(light(Point3::new(2.0, 0.0, 0.0), 1.0), 90.0);
+        assert_pdf_normalized(&moved, Point3::new(0.0, 0.5, 1.0));
+
+        let ellipsoid: Arc<dyn Hittable> =
+            Arc::new(Scale::new(light(Point3::default(), 1.0), Vec3::new(3.0, 1.0, 0.5)).unwrap());
+        let turned = RotateZ::new(ellipsoid, 30.0);
+        assert_pdf_normalized(&turned, Point3::new(1.0, 2.0, 2.0));
+    }
+
+    #[test]
+    fn zero_scale_is_singular() {
+        let sphere = light(Point3::default(), 1.0);
+        for factors in [
+            Vec3::new(0.0, 1.0, 1.0),
+            Vec3::new(1.0, 0.0, 1.0),
+            Vec3::new(1.0, 1.0, 0.0),
+        ] {
+            assert!(matches!(
+                Scale::new(sphere.clone(), factors),
+                Err(SingularMatrix)
+            ));
+        }
+        let transform = Transform::new(sphere, Mat4::rotation_x(45.0)).unwrap();
+        let flattened = transform.then(Mat4::scaling(&Vec3::new(2.0, 0.0, 2.0)));
+        assert!(matches!(flattened, Err(SingularMatrix)));
+    }
+}
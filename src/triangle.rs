@@ -0,0 +1,125 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::Aabb,
+    constants::INFINITY,
+    hittable::{HitRecord, Hittable},
+    interval::Interval,
+    material::Material,
+    ray::Ray,
+    utils::{random_f64, Rng},
+    vector::{Point3, Vec3},
+};
+
+pub struct Triangle {
+    v0: Point3,
+    v1: Point3,
+    v2: Point3,
+    pub material: Arc<dyn Material>,
+    normal: Vec3,
+    area: f64,
+    bbox: Aabb,
+}
+
+impl Triangle {
+    // Vertices in counter clockwise order when seen from the front
+    pub fn new(v0: Point3, v1: Point3, v2: Point3, material: Arc<dyn Material>) -> Triangle {
+        let n = (v1 - v0).cross(&(v2 - v0));
+        let normal = n.normalize();
+        let area = 0.5 * n.length();
+        let bbox = Aabb::enclosing(&Aabb::from_points(&v0, &v1), &Aabb::from_points(&v0, &v2));
+        Triangle {
+            v0,
+            v1,
+            v2,
+            material,
+            normal,
+            area,
+            bbox,
+        }
+    }
+}
+
+// Möller–Trumbore intersection, returning the ray parameter and the barycentric weights of v1
+// and v2
+pub(crate) fn intersect(
+    ray: &Ray,
+    ray_t: &Interval,
+    v0: &Point3,
+    v1: &Point3,
+    v2: &Point3,
+) -> Option<(f64, f64, f64)> {
+    let edge1 = v1 - v0;
+    let edge2 = v2 - v0;
+    let pvec = ray.direction().cross(&edge2);
+    let det = edge1.dot(&pvec);
+
+    // Ray is parallel to the triangle plane
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+
+    let tvec = ray.origin() - v0;
+    let b1 = tvec.dot(&pvec) * inv_det;
+    if !(0.0..=1.0).contains(&b1) {
+        return None;
+    }
+
+    let qvec = tvec.cross(&edge1);
+    let b2 = ray.direction().dot(&qvec) * inv_det;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+
+    let t = edge2.dot(&qvec) * inv_det;
+    if !ray_t.surrounds(t) {
+        return None;
+    }
+    Some((t, b1, b2))
+}
+
+impl Hittable for Triangle {
+    fn hit<'a>(&'a self, ray: &Ray, ray_t: Interval, rec: &mut HitRecord<'a>) -> bool {
+        let Some((t, b1, b2)) = intersect(ray, &ray_t, &self.v0, &self.v1, &self.v2) else {
+            return false;
+        };
+
+        rec.t = t;
+        rec.p = ray.at(t);
+        rec.u = b1;
+        rec.v = b2;
+        rec.set_face_normal(ray, &self.normal);
+        rec.material = self.material.as_ref();
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let Some((t, _, _)) = intersect(
+            &Ray::new(*origin, *direction),
+            &Interval::new(0.001, INFINITY),
+            &self.v0,
+            &self.v1,
+            &self.v2,
+        ) else {
+            return 0.0;
+        };
+
+        // Convert the uniform area density to solid angle
+        let distance_squared = t * t * direction.length_squared();
+        let cosine = (direction.dot(&self.normal) / direction.length()).abs();
+        distance_squared / (cosine * self.area)
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut Rng) -> Vec3 {
+        // Uniform over the area, without rejecting samples outside the triangle
+        let su = random_f64(rng).sqrt();
+        let r2 = random_f64(rng);
+        let p = self.v0 * (1.0 - su) + self.v1 * (su * (1.0 - r2)) + self.v2 * (su * r2);
+        p - *origin
+    }
+}
@@ -1,20 +1,35 @@
-use rand::{self, Rng};
+use rand::rngs::SmallRng;
+use rand::{Rng as _, SeedableRng};
 use std::f64::consts::PI;
 
 use crate::vector::Vec3;
 
-pub fn random_f64() -> f64 {
-    let mut rng = rand::thread_rng();
+// Random number generator threaded through everything that samples while rendering
+pub type Rng = SmallRng;
+
+// Independent generator for one stream (e.g. one sample of one pixel) of a seeded render. The
+// same seed and stream always give the same numbers, whichever thread asks for them.
+pub fn seeded_rng(seed: u64, stream: u64) -> Rng {
+    Rng::seed_from_u64(splitmix64(seed ^ splitmix64(stream)))
+}
+
+// Finaliser of the SplitMix64 generator, scrambles nearby inputs into unrelated outputs
+pub(crate) fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+pub fn random_f64(rng: &mut Rng) -> f64 {
     rng.gen()
 }
 
-pub fn random_f64_in_range(min: f64, max: f64) -> f64 {
-    let mut rng = rand::thread_rng();
+pub fn random_f64_in_range(min: f64, max: f64, rng: &mut Rng) -> f64 {
     rng.gen_range(min..max)
 }
 
-pub fn sample_square() -> Vec3 {
-    let mut rng = rand::thread_rng();
+pub fn sample_square(rng: &mut Rng) -> Vec3 {
     let x = rng.gen_range(-0.5..0.5);
     let y = rng.gen_range(-0.5..0.5);
     Vec3::new(x, y, 0.0)
@@ -24,7 +39,15 @@ pub fn linear_to_gamma(linear_component: f64) -> f64 {
     if linear_component > 0.0 {
         return linear_component.sqrt();
     }
-    return 0.0;
+    0.0
+}
+
+// Inverse of linear_to_gamma
+pub fn gamma_to_linear(gamma_component: f64) -> f64 {
+    if gamma_component > 0.0 {
+        return gamma_component * gamma_component;
+    }
+    0.0
 }
 
 pub fn degrees_to_radians(degrees: f64) -> f64 {
@@ -1,8 +1,9 @@
 #![allow(dead_code)]
 
-use crate::utils::{random_f64, random_f64_in_range};
+use crate::constants::PI;
+use crate::utils::{random_f64, random_f64_in_range, Rng};
 
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
 pub struct Vec3 {
     x: f64,
     y: f64,
@@ -12,7 +13,7 @@ pub struct Vec3 {
 pub type Point3 = Vec3;
 
 impl Vec3 {
-    pub fn new(x: f64, y: f64, z: f64) -> Vec3 {
+    pub const fn new(x: f64, y: f64, z: f64) -> Vec3 {
         Vec3 { x, y, z }
     }
 
@@ -21,25 +22,25 @@ impl Vec3 {
         self.x.abs() < s && self.y.abs() < s && self.z.abs() < s
     }
 
-    pub fn random() -> Vec3 {
+    pub fn random(rng: &mut Rng) -> Vec3 {
         Vec3 {
-            x: random_f64(),
-            y: random_f64(),
-            z: random_f64(),
+            x: random_f64(rng),
+            y: random_f64(rng),
+            z: random_f64(rng),
         }
     }
 
-    pub fn random_in_range(min: f64, max: f64) -> Vec3 {
+    pub fn random_in_range(min: f64, max: f64, rng: &mut Rng) -> Vec3 {
         Vec3 {
-            x: random_f64_in_range(min, max),
-            y: random_f64_in_range(min, max),
-            z: random_f64_in_range(min, max),
+            x: random_f64_in_range(min, max, rng),
+            y: random_f64_in_range(min, max, rng),
+            z: random_f64_in_range(min, max, rng),
         }
     }
 
-    pub fn random_unit_vector() -> Vec3 {
+    pub fn random_unit_vector(rng: &mut Rng) -> Vec3 {
         loop {
-            let p = Vec3::random_in_range(-1.0, 1.0);
+            let p = Vec3::random_in_range(-1.0, 1.0, rng);
             let l = p.length_squared();
             if l > 1e-160 && l <= 1.0 {
                 return p / l.sqrt();
@@ -47,23 +48,60 @@ impl Vec3 {
         }
     }
 
-    pub fn random_unit_vector_on_hemisphere(normal: &Vec3) -> Vec3 {
-        let on_spher = Vec3::random_unit_vector();
+    pub fn random_unit_vector_on_hemisphere(normal: &Vec3, rng: &mut Rng) -> Vec3 {
+        let on_spher = Vec3::random_unit_vector(rng);
         if on_spher.dot(normal) > 0.0 {
             return on_spher;
         }
         on_spher * -1.0
     }
 
-    pub fn random_in_unit_disk() -> Vec3 {
+    pub fn random_in_unit_disk(rng: &mut Rng) -> Vec3 {
         loop {
-            let p = Vec3::new(random_f64(), random_f64(), 0.0);
+            let p = Vec3::new(
+                random_f64_in_range(-1.0, 1.0, rng),
+                random_f64_in_range(-1.0, 1.0, rng),
+                0.0,
+            );
             if p.length_squared() < 1.0 {
                 return p;
             }
         }
     }
 
+    // Uniform point on the unit sphere from a point (u, v) of the unit square, so stratified
+    // samples stay stratified
+    pub fn sample_unit_vector((u, v): (f64, f64)) -> Vec3 {
+        let z = 1.0 - 2.0 * u;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * v;
+        Vec3::new(r * phi.cos(), r * phi.sin(), z)
+    }
+
+    // Uniform point on the unit disk from a point of the unit square, using Shirley and Chiu's
+    // concentric mapping which keeps neighbouring points together
+    pub fn sample_unit_disk((u, v): (f64, f64)) -> Vec3 {
+        let a = 2.0 * u - 1.0;
+        let b = 2.0 * v - 1.0;
+        if a == 0.0 && b == 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let (r, theta) = if a.abs() > b.abs() {
+            (a, PI / 4.0 * (b / a))
+        } else {
+            (b, PI / 2.0 - PI / 4.0 * (a / b))
+        };
+        Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
+    }
+
+    // Cosine weighted direction on the hemisphere around +z from a point of the unit square,
+    // lifting a concentric disk sample up onto the hemisphere (Malley's method)
+    pub fn sample_cosine_hemisphere(sample: (f64, f64)) -> Vec3 {
+        let d = Vec3::sample_unit_disk(sample);
+        let z = (1.0 - d.x() * d.x() - d.y() * d.y()).max(0.0).sqrt();
+        Vec3::new(d.x(), d.y(), z)
+    }
+
     pub fn x(&self) -> f64 {
         self.x
     }
@@ -89,7 +127,7 @@ impl Vec3 {
     }
 
     pub fn length_squared(&self) -> f64 {
-        self.dot(&self)
+        self.dot(self)
     }
 
     pub fn length(&self) -> f64 {
@@ -102,7 +140,7 @@ impl Vec3 {
 
     pub fn reflect(&self, normal: &Vec3) -> Vec3 {
         let b = &self.dot(normal) * 2.0;
-        self.clone() - (normal * b)
+        *self - (normal * b)
     }
 
     pub fn refract(&self, normal: &Vec3, etai_over_etat: f64) -> Vec3 {
@@ -112,6 +150,19 @@ impl Vec3 {
         r_out_perp + r_out_parallel
     }
 }
+impl std::ops::Index<usize> for Vec3 {
+    type Output = f64;
+
+    fn index(&self, axis: usize) -> &f64 {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vec3 axis index out of range: {}", axis),
+        }
+    }
+}
+
 impl std::ops::Add for Vec3 {
     type Output = Vec3;
 